@@ -8,12 +8,17 @@ extern crate awc;
 extern crate actix_codec;
 extern crate futures;
 extern crate config;
+extern crate rustls;
+extern crate webpki_roots;
 
 mod reader;
 mod writer;
 
 use std::time::Duration;
 use std::thread::sleep;
+use std::sync::Arc;
+use std::fs::File;
+use std::io::BufReader;
 
 use awc::Client;
 use actix::prelude::*;
@@ -27,7 +32,9 @@ use std::process::exit;
 pub struct Settings {
     pub telemetry_update_interval: u64,
     pub session_update_interval: u64,
-    pub telemetry_service_url: String
+    pub telemetry_service_url: String,
+    pub stream_password: String,
+    pub ca_bundle: Option<String>
 }
 
 pub fn main() {
@@ -38,6 +45,7 @@ pub fn main() {
     let _ = cfg.set_default("telemetry_service_url", "ws://127.0.0.1:8088/source");
     let _ = cfg.set_default("session_update_interval", 5000);
     let _ = cfg.set_default("telemetry_update_interval", 250);
+    let _ = cfg.set_default("stream_password", "");
 
     cfg.merge(config::File::with_name("exporter")).unwrap();
 
@@ -51,16 +59,38 @@ pub fn main() {
 
     let system = System::new("Exporter");
     let url = settings.clone().telemetry_service_url;
+    let password = settings.clone().stream_password;
+
+    let tls = match &settings.ca_bundle {
+        None => None,
+        Some(path) => match tls_config(path) {
+            Ok(tls) => Some(Arc::new(tls)),
+            Err(e) => {
+                error!("Unable to load CA bundle {}: {}", path, e);
+                return;
+            }
+        }
+    };
 
     Arbiter::spawn(async {
         info!("Connecting to service @ {}", url);
 
+        let mut connector = awc::Connector::new().timeout(Duration::from_secs(10));
+
+        if let Some(tls) = tls {
+            connector = connector.rustls(tls);
+        }
 
-        let (_, framed) =
-               Client::build().connector(
-                   awc::Connector::new().timeout(Duration::from_secs(10)).finish())
+        let mut request =
+               Client::build().connector(connector.finish())
                    .timeout(Duration::from_secs(10)).finish()
-                   .ws(url).connect().await
+                   .ws(url);
+
+        if !password.is_empty() {
+            request = request.bearer_auth(password);
+        }
+
+        let (_, framed) = request.connect().await
                 .map_err(|e| { 
                     error!("Unable to connect to socket: {}", e); 
                     exit(10);
@@ -105,4 +135,22 @@ fn get_iracing_connection() -> iracing::Connection {
    } 
 
    conn
-}
\ No newline at end of file
+}
+
+/// Build a TLS configuration trusting the CA certificates in a PEM bundle, as well as the usual web roots.
+fn tls_config(ca_bundle: &str) -> std::io::Result<rustls::ClientConfig> {
+    let mut config = rustls::ClientConfig::new();
+    config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+    let mut reader = BufReader::new(File::open(ca_bundle)?);
+
+    match config.root_store.add_pem_file(&mut reader) {
+        Ok((valid, _)) if valid > 0 => (),
+        _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "No valid CA certificates found"))
+    }
+
+    // WebSockets are only upgraded over HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(config)
+}
@@ -1,46 +1,23 @@
 use std::time::Duration;
 use serde::{Serialize, Deserialize};
 use actix::prelude::*;
-use iracing::session::SessionDetails;
 use iracing::telemetry::Value;
 use iracing;
-
-#[derive(Message,Debug,Default,Serialize,Deserialize,Clone)]
-#[rtype(result = "()")]
-pub struct TelemetryMessage {
-    pub air_temperature: f32,
-    pub state: i32,
-    pub flags: u32,
-    pub time_remaining: f64,
-    pub track_temperature: f32,
-    pub session_number: i32,
-    pub car_class_positions: Vec<i32>,
-    pub car_positions: Vec<i32>,
-    pub car_gears: Vec<i32>,
-    pub car_rpms: Vec<f32>,
-    pub car_steers: Vec<f32>,
-    pub car_laps: Vec<i32>,
-    pub car_laps_perc: Vec<f32>,
-    pub car_pits: Vec<bool>
-}
-
-#[derive(Message,Debug,Serialize,Deserialize,Clone)]
-#[rtype(result = "()")]
-pub struct SessionMessage(SessionDetails);
+use protocol::{TelemetryData, SessionDetails};
 
 #[derive(Message,Debug,Serialize,Deserialize,Clone)]
-#[rtype(result = "TelemetryMessage")]
+#[rtype(result = "TelemetryData")]
 pub struct TelemetryRequest;
 
 #[derive(Debug,Serialize,Deserialize,Clone)]
 pub struct SessionRequest;
 
 impl Message for SessionRequest {
-    type Result = Option<SessionMessage>;
+    type Result = Option<SessionDetails>;
 }
 
 pub struct TelemetryReader {
-    writer: Recipient<TelemetryMessage>,
+    writer: Recipient<TelemetryData>,
     src: Recipient<TelemetryRequest>,
     interval: Duration
 }
@@ -54,7 +31,7 @@ impl Actor for TelemetryReader {
 }
 
 impl TelemetryReader {
-    pub fn new(intr: Duration, src: Recipient<TelemetryRequest>, writer_addr: Recipient<TelemetryMessage>) -> Self {
+    pub fn new(intr: Duration, src: Recipient<TelemetryRequest>, writer_addr: Recipient<TelemetryData>) -> Self {
         TelemetryReader { src: src, writer: writer_addr, interval: intr }
     }
 
@@ -85,11 +62,11 @@ impl TelemetryReader {
 
 pub struct SessionReader {
     src: Recipient<SessionRequest>,
-    writer: Recipient<SessionMessage>
+    writer: Recipient<SessionDetails>
 }
 
 impl SessionReader {
-    pub fn new(src_addr: Recipient<SessionRequest>, writer_addr: Recipient<SessionMessage>) -> Self {
+    pub fn new(src_addr: Recipient<SessionRequest>, writer_addr: Recipient<SessionDetails>) -> Self {
         Self { src: src_addr, writer: writer_addr }
     }
 }
@@ -192,7 +169,7 @@ impl Handler<TelemetryRequest> for IRacingReader {
                     }
                 };
 
-                let data = TelemetryMessage {
+                let mut data = TelemetryData {
                     air_temperature: air_temperature,
                     flags: raw_flags,
                     track_temperature: track_temp,
@@ -206,9 +183,12 @@ impl Handler<TelemetryRequest> for IRacingReader {
                     car_rpms: match car_rpms { Value::FloatVec(floats) => floats, _ => vec![0f32; 64] },
                     car_laps: match car_laps { Value::IntVec(ints) => ints, _ => vec![0i32; 64] },
                     car_laps_perc: match car_laps_perc { Value::FloatVec(floats) => floats, _ => vec![0f32; 64] },
-                    car_steers: match car_steers { Value::FloatVec(floats) => floats, _ => vec![0f32; 64] }
+                    car_steers: match car_steers { Value::FloatVec(floats) => floats, _ => vec![0f32; 64] },
+                    ..TelemetryData::default()
                 };
 
+                data.fill_decoded();
+
                 MessageResult(data)
             }
         }
@@ -216,12 +196,17 @@ impl Handler<TelemetryRequest> for IRacingReader {
 }
 
 impl Handler<SessionRequest> for IRacingReader {
-    type Result = Option<SessionMessage>;
+    type Result = Option<SessionDetails>;
 
     fn handle(&mut self, _: SessionRequest, _: &mut Self::Context) -> Self::Result {
         match self.conn.session_info() {
-            Ok(session) => {
-                Some( SessionMessage( session ) )
+            // The session is converted through JSON, as the server's session types share iRacing's field names.
+            Ok(session) => match serde_json::to_value(session).and_then(serde_json::from_value) {
+                Ok(details) => Some(details),
+                Err(e) => {
+                    error!("Unable to convert session info: {}", e);
+                    None
+                }
             }
 
             Err(e) => {
@@ -1,64 +1,124 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use actix::prelude::*;
 use actix_codec::Framed;
 use actix::io::SinkWrite;
-use crate::reader::{TelemetryMessage, SessionMessage};
+use protocol::{TelemetryData, SessionDetails};
+use protocol::envelope::{self, Hello};
 use awc::{error::WsProtocolError, ws::{Codec,Frame,Message}, BoxedSocket};
 use futures::stream::SplitSink;
-use serde_json::to_string as json;
 
-pub struct WebsocketWriter(SinkWrite<Message, SplitSink<Framed<BoxedSocket, Codec>, Message>>);
+///
+/// Writes telemetry and session updates to the server.
+///
+/// Updates are sent in the legacy framing until the server answers the writer's hello with a welcome,
+/// so older servers which don't know the handshake keep working.
+pub struct WebsocketWriter {
+    sink: SinkWrite<Message, SplitSink<Framed<BoxedSocket, Codec>, Message>>,
+    enveloped: bool,
+    seq: u64
+}
 
 impl WebsocketWriter {
     pub fn new(s: SinkWrite<Message, SplitSink<Framed<BoxedSocket, Codec>, Message>>) -> Self {
-        Self(s)
+        Self { sink: s, enveloped: false, seq: 0 }
+    }
+
+    /// Send a message, in an envelope once the server has welcomed the writer.
+    fn send(&mut self, msg: envelope::Message) {
+        let content = if self.enveloped {
+            self.seq += 1;
+            envelope::encode(&msg, self.seq, now_millis())
+        } else {
+            match msg {
+                envelope::Message::Telemetry(t) => protocol::encode(&protocol::Frame::Telemetry(t)),
+                envelope::Message::Session(s) => protocol::encode(&protocol::Frame::Session(s)),
+                _ => envelope::encode(&msg, 0, now_millis())
+            }
+        };
+
+        let content = match content {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Unable to encode message: {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = self.sink.write(Message::Text(content)) {
+            warn!("Unable to send message: {}", e);
+        }
     }
 }
 
 impl Actor for WebsocketWriter {
     type Context = Context<Self>;
+
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        let hello = Hello::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), &["json"]);
+
+        self.send(envelope::Message::Hello(hello));
+    }
 }
 
 impl StreamHandler<Result<Frame, WsProtocolError>> for WebsocketWriter {
-    fn handle(&mut self, _: Result<Frame, WsProtocolError>, _: &mut Context<Self>) {
-
+    fn handle(&mut self, frame: Result<Frame, WsProtocolError>, _: &mut Context<Self>) {
+        match frame {
+            // The server disconnects sources which don't answer its pings.
+            Ok(Frame::Ping(ping)) => {
+                if let Err(e) = self.sink.write(Message::Pong(ping)) {
+                    warn!("Unable to send pong: {}", e);
+                }
+            }
+
+            Ok(Frame::Text(text)) => {
+                let text = String::from_utf8_lossy(&text);
+
+                match envelope::decode(&text).map(|e| e.data) {
+                    Ok(envelope::Message::Welcome(welcome)) => {
+                        info!("Connected to {} {} (protocol version {})", welcome.name, welcome.version, welcome.protocol_version);
+
+                        // Servers which speak another version are sent legacy frames, which every version accepts.
+                        if welcome.protocol_version == envelope::PROTOCOL_VERSION {
+                            self.enveloped = true;
+                        } else {
+                            warn!("Server speaks protocol version {}, not {}; sending legacy frames", welcome.protocol_version, envelope::PROTOCOL_VERSION);
+                        }
+                    }
+
+                    Ok(_) => warn!("Unexpected message from server"),
+                    Err(e) => warn!("Invalid message from server: {}", e)
+                }
+            }
+
+            _ => ()
+        }
     }
 }
 
-impl Handler<TelemetryMessage> for WebsocketWriter {
+impl Handler<TelemetryData> for WebsocketWriter {
     type Result = ();
 
-    fn handle(&mut self, msg: TelemetryMessage, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: TelemetryData, _ctx: &mut Self::Context) {
         trace!("Sending Telemetry: {:?}", msg);
 
-        let data: String = json(&msg).unwrap();
-        let mut content = "T".to_owned();
-
-        content.push_str(&data);
-
-        match self.0.write(Message::Text(content)) {
-            Ok(_) => (),
-            Err(e) => warn!("Unable to send telemtry: {}", e)
-        }
+        self.send(envelope::Message::Telemetry(msg));
     }
 }
 
-impl Handler<SessionMessage> for WebsocketWriter {
+impl Handler<SessionDetails> for WebsocketWriter {
     type Result = ();
 
-    fn handle(&mut self, msg: SessionMessage, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: SessionDetails, _ctx: &mut Self::Context) {
         trace!("Sending Session");
 
-        let data: String = json(&msg).unwrap();
-
-        let mut content = "S".to_owned();
-
-        content.push_str(&data);
-
-       match self.0.write(Message::Text(content)) {
-           Ok(_) => (),
-           Err(e) => warn!("Unable to send session: {}", e)
-       };
+        self.send(envelope::Message::Session(msg));
     }
 }
 
-impl actix::io::WriteHandler<WsProtocolError> for WebsocketWriter {}
\ No newline at end of file
+impl actix::io::WriteHandler<WsProtocolError> for WebsocketWriter {}
+
+/// Milliseconds since the UNIX epoch.
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
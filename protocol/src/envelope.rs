@@ -0,0 +1,163 @@
+//! Versioned envelopes, which wrap each message with its type, sequence number and timestamp.
+//!
+//! Either side of a connection introduces itself with a `hello` and is answered with a
+//! `welcome`, after which messages are sent in envelopes. Peers which never send a `hello`
+//! keep using the legacy framing.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Error, Frame, SessionDetails, TelemetryData, SESSION, TELEMETRY};
+
+/// Version of the envelope format and handshake.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const HELLO: &str = "hello";
+pub const WELCOME: &str = "welcome";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub version: u32,
+
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    /// Position of the message in the sender's stream.
+    pub seq: u64,
+
+    /// Time the message was sent (ms since the UNIX epoch).
+    pub timestamp: u64,
+
+    pub data: T
+}
+
+///
+/// Introduction sent by each side of a connection.
+///
+/// A `hello` describes the client, the `welcome` in reply describes the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+    pub name: String,
+    pub version: String,
+    pub protocol_version: u32,
+    pub encodings: Vec<String>
+}
+
+/// Messages exchanged between a source and the server.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Hello(Hello),
+    Welcome(Hello),
+    Telemetry(TelemetryData),
+    Session(SessionDetails)
+}
+
+/// Name of the envelope type for a legacy frame type character.
+pub fn type_name(kind: char) -> Option<&'static str> {
+    match kind {
+        'T' => Some("telemetry"),
+        'S' => Some("session"),
+        'F' => Some("feed"),
+        'L' => Some("timing"),
+        'P' => Some("standings"),
+        'E' => Some("event"),
+        'K' => Some("keyframe"),
+        'D' => Some("delta"),
+        _ => None
+    }
+}
+
+impl Hello {
+    /// Introduce a peer which speaks this version of the protocol.
+    pub fn new(name: &str, version: &str, encodings: &[&str]) -> Self {
+        Self {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            protocol_version: PROTOCOL_VERSION,
+            encodings: encodings.iter().map(|e| e.to_string()).collect()
+        }
+    }
+}
+
+impl<T> Envelope<T> {
+    pub fn new(kind: &str, seq: u64, timestamp: u64, data: T) -> Self {
+        Self { version: PROTOCOL_VERSION, kind: kind.to_owned(), seq, timestamp, data }
+    }
+}
+
+impl Message {
+    fn kind(&self) -> &'static str {
+        match self {
+            Message::Hello(_) => HELLO,
+            Message::Welcome(_) => WELCOME,
+            Message::Telemetry(_) => "telemetry",
+            Message::Session(_) => "session"
+        }
+    }
+}
+
+impl From<Frame> for Message {
+    fn from(frame: Frame) -> Self {
+        match frame {
+            Frame::Telemetry(telem) => Message::Telemetry(telem),
+            Frame::Session(session) => Message::Session(session)
+        }
+    }
+}
+
+/// Encode a message in an envelope.
+pub fn encode(msg: &Message, seq: u64, timestamp: u64) -> Result<String, Error> {
+    let data = match msg {
+        Message::Hello(hello) | Message::Welcome(hello) => serde_json::to_value(hello),
+        Message::Telemetry(telem) => serde_json::to_value(telem),
+        Message::Session(session) => serde_json::to_value(session)
+    };
+
+    let data = data.map_err(Error::Envelope)?;
+
+    serde_json::to_string(&Envelope::new(msg.kind(), seq, timestamp, data)).map_err(Error::Envelope)
+}
+
+///
+/// Decode a message from its envelope. The decoded forms of telemetry's flags and state are filled in.
+///
+/// Hellos and welcomes are decoded whatever their version, so peers can tell each other which version they speak.
+/// Other messages must be of this version.
+pub fn decode(text: &str) -> Result<Envelope<Message>, Error> {
+    let envelope: Envelope<Value> = serde_json::from_str(text).map_err(Error::Envelope)?;
+    let handshake = envelope.kind == HELLO || envelope.kind == WELCOME;
+
+    if !handshake && envelope.version != PROTOCOL_VERSION {
+        return Err(Error::UnsupportedVersion(envelope.version));
+    }
+
+    let data = match envelope.kind.as_str() {
+        HELLO => Message::Hello(serde_json::from_value(envelope.data).map_err(Error::Envelope)?),
+        WELCOME => Message::Welcome(serde_json::from_value(envelope.data).map_err(Error::Envelope)?),
+
+        "telemetry" => {
+            let mut telem: TelemetryData = serde_json::from_value(envelope.data).map_err(|e| Error::Invalid(TELEMETRY, e))?;
+            telem.fill_decoded();
+
+            Message::Telemetry(telem)
+        },
+
+        "session" => Message::Session(serde_json::from_value(envelope.data).map_err(|e| Error::Invalid(SESSION, e))?),
+
+        _ => return Err(Error::UnknownMessage(envelope.kind))
+    };
+
+    Ok(Envelope {
+        version: envelope.version,
+        kind: envelope.kind,
+        seq: envelope.seq,
+        timestamp: envelope.timestamp,
+        data
+    })
+}
+
+/// Check whether text is an envelope rather than a legacy frame.
+pub fn is_envelope(text: &str) -> bool {
+    text.starts_with('{')
+}
@@ -0,0 +1,118 @@
+//! Typed forms of the session flags and state sent as raw numbers in telemetry.
+
+use bitflags::bitflags;
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeSeq;
+
+bitflags! {
+    /// Flags shown during the session, as in iRacing's `SessionFlags`.
+    #[derive(Default)]
+    pub struct SessionFlags: u32 {
+        const CHECKERED = 0x0000_0001;
+        const WHITE = 0x0000_0002;
+        const GREEN = 0x0000_0004;
+        const YELLOW = 0x0000_0008;
+        const RED = 0x0000_0010;
+        const BLUE = 0x0000_0020;
+        const DEBRIS = 0x0000_0040;
+        const CROSSED = 0x0000_0080;
+        const YELLOW_WAVING = 0x0000_0100;
+        const ONE_LAP_TO_GREEN = 0x0000_0200;
+        const GREEN_HELD = 0x0000_0400;
+        const TEN_TO_GO = 0x0000_0800;
+        const FIVE_TO_GO = 0x0000_1000;
+        const RANDOM_WAVING = 0x0000_2000;
+        const CAUTION = 0x0000_4000;
+        const CAUTION_WAVING = 0x0000_8000;
+
+        const BLACK = 0x0001_0000;
+        const DISQUALIFY = 0x0002_0000;
+        const SERVICIBLE = 0x0004_0000;
+        const FURLED = 0x0008_0000;
+        const REPAIR = 0x0010_0000;
+
+        const START_HIDDEN = 0x1000_0000;
+        const START_READY = 0x2000_0000;
+        const START_SET = 0x4000_0000;
+        const START_GO = 0x8000_0000;
+    }
+}
+
+/// Names of the flags, as they are serialized.
+const FLAG_NAMES: [(SessionFlags, &str); 25] = [
+    (SessionFlags::CHECKERED, "checkered"),
+    (SessionFlags::WHITE, "white"),
+    (SessionFlags::GREEN, "green"),
+    (SessionFlags::YELLOW, "yellow"),
+    (SessionFlags::RED, "red"),
+    (SessionFlags::BLUE, "blue"),
+    (SessionFlags::DEBRIS, "debris"),
+    (SessionFlags::CROSSED, "crossed"),
+    (SessionFlags::YELLOW_WAVING, "yellow_waving"),
+    (SessionFlags::ONE_LAP_TO_GREEN, "one_lap_to_green"),
+    (SessionFlags::GREEN_HELD, "green_held"),
+    (SessionFlags::TEN_TO_GO, "ten_to_go"),
+    (SessionFlags::FIVE_TO_GO, "five_to_go"),
+    (SessionFlags::RANDOM_WAVING, "random_waving"),
+    (SessionFlags::CAUTION, "caution"),
+    (SessionFlags::CAUTION_WAVING, "caution_waving"),
+    (SessionFlags::BLACK, "black"),
+    (SessionFlags::DISQUALIFY, "disqualify"),
+    (SessionFlags::SERVICIBLE, "servicible"),
+    (SessionFlags::FURLED, "furled"),
+    (SessionFlags::REPAIR, "repair"),
+    (SessionFlags::START_HIDDEN, "start_hidden"),
+    (SessionFlags::START_READY, "start_ready"),
+    (SessionFlags::START_SET, "start_set"),
+    (SessionFlags::START_GO, "start_go")
+];
+
+impl SessionFlags {
+    /// Names of the flags which are set.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        FLAG_NAMES.iter()
+            .filter(move |(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+    }
+}
+
+/// Flags are serialized as a list of the names of those which are set, e.g. `["green", "blue"]`.
+impl Serialize for SessionFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+
+        for name in self.names() {
+            seq.serialize_element(name)?;
+        }
+
+        seq.end()
+    }
+}
+
+/// State of the session, as in iRacing's `SessionState`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    #[default]
+    Invalid,
+    GetInCar,
+    Warmup,
+    ParadeLaps,
+    Racing,
+    Checkered,
+    CoolDown
+}
+
+impl From<i32> for SessionState {
+    fn from(state: i32) -> Self {
+        match state {
+            1 => SessionState::GetInCar,
+            2 => SessionState::Warmup,
+            3 => SessionState::ParadeLaps,
+            4 => SessionState::Racing,
+            5 => SessionState::Checkered,
+            6 => SessionState::CoolDown,
+            _ => SessionState::Invalid
+        }
+    }
+}
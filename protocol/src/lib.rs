@@ -0,0 +1,113 @@
+//! Wire types and framing shared by the exporter, the server and their clients.
+//!
+//! In the legacy framing, each telemetry or session update is sent as a text frame holding
+//! a single type character, `T` or `S`, followed by the JSON of the update. Peers which
+//! complete a handshake instead send versioned envelopes, see `envelope`.
+
+use std::fmt;
+
+pub mod envelope;
+pub mod flags;
+pub mod session;
+pub mod telemetry;
+pub mod units;
+
+pub use flags::{SessionFlags, SessionState};
+pub use session::SessionDetails;
+pub use telemetry::TelemetryData;
+
+/// Type character of telemetry frames.
+pub const TELEMETRY: char = 'T';
+
+/// Type character of session frames.
+pub const SESSION: char = 'S';
+
+// Frames only live between being decoded and handed on, so their size isn't a concern.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Telemetry(TelemetryData),
+    Session(SessionDetails)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The frame was empty.
+    Empty,
+
+    /// The frame's type character isn't known.
+    UnknownType(char),
+
+    /// The payload of a frame of the given type couldn't be encoded or decoded.
+    Invalid(char, serde_json::Error),
+
+    /// An envelope couldn't be encoded or decoded.
+    Envelope(serde_json::Error),
+
+    /// An envelope was sent with an unsupported protocol version.
+    UnsupportedVersion(u32),
+
+    /// An envelope's message type isn't known.
+    UnknownMessage(String)
+}
+
+/// Encode a frame as text.
+pub fn encode(frame: &Frame) -> Result<String, Error> {
+    match frame {
+        Frame::Telemetry(telem) => encode_payload(TELEMETRY, telem),
+        Frame::Session(session) => encode_payload(SESSION, session)
+    }
+}
+
+/// Decode a text frame. The decoded forms of telemetry's flags and state are filled in.
+pub fn decode(text: &str) -> Result<Frame, Error> {
+    let kind = text.chars().next().ok_or(Error::Empty)?;
+    let payload = &text[kind.len_utf8()..];
+
+    match kind {
+        TELEMETRY => {
+            let mut telem: TelemetryData = serde_json::from_str(payload).map_err(|e| Error::Invalid(kind, e))?;
+            telem.fill_decoded();
+
+            Ok(Frame::Telemetry(telem))
+        },
+
+        SESSION => serde_json::from_str(payload)
+            .map(Frame::Session)
+            .map_err(|e| Error::Invalid(kind, e)),
+
+        _ => Err(Error::UnknownType(kind))
+    }
+}
+
+fn encode_payload<T: serde::Serialize>(kind: char, payload: &T) -> Result<String, Error> {
+    let json = serde_json::to_string(payload).map_err(|e| Error::Invalid(kind, e))?;
+
+    let mut text = String::with_capacity(json.len() + 1);
+    text.push(kind);
+    text.push_str(&json);
+
+    Ok(text)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Empty => write!(f, "Empty frame"),
+            Error::UnknownType(kind) => write!(f, "Unknown frame type '{}'", kind),
+            Error::Invalid(kind, e) => write!(f, "Invalid '{}' frame: {}", kind, e),
+            Error::Envelope(e) => write!(f, "Invalid envelope: {}", e),
+            Error::UnsupportedVersion(version) => write!(f, "Unsupported protocol version {}", version),
+            Error::UnknownMessage(kind) => write!(f, "Unknown message type '{}'", kind)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Invalid(_, e) | Error::Envelope(e) => Some(e),
+            _ => None
+        }
+    }
+}
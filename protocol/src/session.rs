@@ -1,11 +1,13 @@
 use serde::{Deserialize, Serialize};
-use actix::Message;
+
+use crate::units::{Measure, Quantity, Unit};
+
 ///
 /// Session Details
 /// 
 /// Top-level details regarding the current session, including race weekend, session and drivers.
-#[derive(Debug, Clone, Serialize, Deserialize, Message)]
-#[rtype(result = "()")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "actix", derive(actix::Message), rtype(result = "()"))]
 pub struct SessionDetails {
     #[serde(rename = "WeekendInfo")]
     pub weekend: WeekendInfo, // Race Weekend Info (track, location, series etc.)
@@ -20,7 +22,7 @@ pub struct SessionDetails {
 ///
 /// Details of the race weekend. Including details of the track being raced,
 /// the weather, racing series, and the rules in play for the session.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct WeekendInfo {
     pub track_name: String, // Track Name
@@ -28,22 +30,22 @@ pub struct WeekendInfo {
     #[serde(rename = "TrackID")]
     pub track_id: u32, // iRacing Track ID
 
-    pub track_length: String,             // Track length (as string of km)
+    pub track_length: Quantity,           // Track length (km)
     pub track_display_name: String,       // Track display name
     pub track_display_short_name: String, // Track short display name
     pub track_config_name: String,        // Track layout/configuration name
     pub track_city: String,               // Track Location, City
     pub track_country: String,            // Track Location: Country
-    pub track_altitude: String,           // Track Altitude (m)
-    pub track_latitude: String,           // Track Latitude (deg)
-    pub track_longitude: String,          // Track Longitude (deg)
-    pub track_north_offset: String,       // Track rotation relative to true north (rad)
+    pub track_altitude: Quantity,         // Track Altitude (m)
+    pub track_latitude: Quantity,         // Track Latitude (deg)
+    pub track_longitude: Quantity,        // Track Longitude (deg)
+    pub track_north_offset: Quantity,     // Track rotation relative to true north (rad)
 
     #[serde(rename = "TrackNumTurns")]
     pub track_turns: u32, // Number of turns
 
-    pub track_pit_speed_limit: String, // Pit speed limit (km/h)
-    pub track_type: String,            // Track type (Road, Oval, Dirt, DOval)
+    pub track_pit_speed_limit: Quantity, // Pit speed limit (km/h)
+    pub track_type: String,              // Track type (Road, Oval, Dirt, DOval)
 
     #[serde(rename = "TrackWeatherType")]
     pub track_weather: String, // Track Weather
@@ -51,20 +53,20 @@ pub struct WeekendInfo {
     pub track_skies: String, // Sky state
 
     #[serde(rename = "TrackSurfaceTemp")]
-    pub track_surface_temperature: String, // Track surface temperature (degC)
+    pub track_surface_temperature: Quantity, // Track surface temperature (degC)
 
     #[serde(rename = "TrackAirTemp")]
-    pub track_air_tempearture: String, // Track air temperature (degC)
+    pub track_air_tempearture: Quantity, // Track air temperature (degC)
 
-    pub track_air_pressure: String, // Track air pressure (Hg)
+    pub track_air_pressure: Quantity, // Track air pressure (Hg)
 
     #[serde(rename = "TrackWindVel")]
-    pub track_wind_speed: String, // Track wind speed (km/h)
+    pub track_wind_speed: Quantity, // Track wind speed (km/h)
 
     #[serde(rename = "TrackWindDir")]
-    pub track_wind_direction: String, // Track wind direction relative to north (rad)
-    pub track_fog_level: String, // Track fogginess
-    pub track_cleanup: i32,      // Track cleanup
+    pub track_wind_direction: Quantity, // Track wind direction relative to north (rad)
+    pub track_fog_level: Quantity, // Track fogginess
+    pub track_cleanup: i32,        // Track cleanup
 
     #[serde(rename = "TrackDynamicTrack")]
     pub track_dynamic: i32, // Track Dynamic
@@ -106,7 +108,7 @@ pub struct WeekendInfo {
     pub options: WeekendOptions,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct WeekendOptions {
     #[serde(rename = "NumStarters")]
@@ -120,13 +122,13 @@ pub struct WeekendOptions {
     pub weather_type: String,   // Weather type
     pub skies: String,          // Skies
     pub wind_direction: String, // Wind direction
-    pub wind_speed: String,     // Wind Speed
+    pub wind_speed: Quantity,   // Wind Speed
 
     #[serde(rename = "WeatherTemp")]
-    pub temperature: String, // Temperature
+    pub temperature: Quantity, // Temperature
 
-    pub relative_humidity: String, // RH (%)
-    pub fog_level: String,
+    pub relative_humidity: Quantity, // RH (%)
+    pub fog_level: Quantity,
     pub unofficial: i8,          // Inverse of Official
     pub commercial_mode: String, // On if race is being run commercially (e.g. Professional race)
     pub night_mode: String,      // On if race is at night
@@ -136,7 +138,7 @@ pub struct WeekendOptions {
     pub hardcore_level: i8,        // Hardcoreness
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct SessionInfo {
     #[serde(rename = "NumSessions")]
@@ -144,7 +146,7 @@ pub struct SessionInfo {
     pub sessions: Vec<Session>, // Sessions
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Session {
     #[serde(rename = "SessionNum")]
@@ -165,7 +167,7 @@ pub struct Session {
     pub results: Vec<SessionResult>
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct SessionResult {
     pub position: i32,
@@ -190,7 +192,7 @@ pub struct SessionResult {
 ///
 /// Struct contains player driver information, as well as a vector of
 /// other drivers in the session
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DriverInfo {
     #[serde(rename = "DriverCarIdx")]
     pub car_index: usize, // Drivers' Car Index
@@ -254,7 +256,7 @@ pub struct DriverInfo {
 /// Details of all drivers (players) in the session, including the current driver.
 ///
 /// Contains details of the user-profile of the driver, their License class, Safety Rating, and iRating.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Driver {
     #[serde(rename = "CarIdx")]
@@ -312,6 +314,9 @@ pub struct Driver {
 
     pub is_spectator: i8, // Is Specator?
 
+    #[serde(default)]
+    pub car_is_pace_car: i8, // Is the pace car
+
     #[serde(rename = "CarDesignStr")]
     pub car_design: String,
 
@@ -325,6 +330,16 @@ pub struct Driver {
     pub division_name: Option<String> // User's disivision name - Not present for safety car.
 }
 
+impl Driver {
+    ///
+    /// Check whether the car is the pace car.
+    ///
+    /// Not every source sends `CarIsPaceCar`, so the pace car is also recognised as a safety car without a user.
+    pub fn is_pace_car(&self) -> bool {
+        self.car_is_pace_car != 0 || (self.user_id == -1 && self.car_path.starts_with("safety pc"))
+    }
+}
+
 impl Session {
     ///
     /// Get the maximum number of laps for the session.
@@ -334,4 +349,57 @@ impl Session {
     pub fn max_laps(&self) -> Option<u64> {
         self.laps.as_u64()
     }
-}
\ No newline at end of file
+}
+
+///
+/// Track and weather conditions from the weekend info, parsed into numbers with units.
+///
+/// Values which couldn't be parsed are `None`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conditions {
+    pub track_length: Option<Measure>,
+    pub track_altitude: Option<Measure>,
+    pub track_latitude: Option<Measure>,
+    pub track_longitude: Option<Measure>,
+    pub track_north_offset: Option<Measure>,
+    pub track_pit_speed_limit: Option<Measure>,
+    pub track_surface_temperature: Option<Measure>,
+    pub track_air_temperature: Option<Measure>,
+    pub track_air_pressure: Option<Measure>,
+    pub track_wind_speed: Option<Measure>,
+    pub track_wind_direction: Option<Measure>,
+    pub track_fog_level: Option<Measure>,
+    pub wind_speed: Option<Measure>,
+    pub temperature: Option<Measure>,
+    pub relative_humidity: Option<Measure>,
+    pub fog_level: Option<Measure>
+}
+
+impl WeekendInfo {
+    /// Get the track and weather conditions as numbers with units.
+    pub fn conditions(&self) -> Conditions {
+        Conditions {
+            track_length: self.track_length.measure(),
+            track_altitude: self.track_altitude.measure(),
+            track_latitude: self.track_latitude.measure().map(degrees),
+            track_longitude: self.track_longitude.measure().map(degrees),
+            track_north_offset: self.track_north_offset.measure(),
+            track_pit_speed_limit: self.track_pit_speed_limit.measure(),
+            track_surface_temperature: self.track_surface_temperature.measure(),
+            track_air_temperature: self.track_air_tempearture.measure(),
+            track_air_pressure: self.track_air_pressure.measure(),
+            track_wind_speed: self.track_wind_speed.measure(),
+            track_wind_direction: self.track_wind_direction.measure(),
+            track_fog_level: self.track_fog_level.measure(),
+            wind_speed: self.options.wind_speed.measure(),
+            temperature: self.options.temperature.measure(),
+            relative_humidity: self.options.relative_humidity.measure(),
+            fog_level: self.options.fog_level.measure()
+        }
+    }
+}
+
+/// iRacing sends the track's latitude and longitude in degrees, but with an `m` suffix.
+fn degrees(measure: Measure) -> Measure {
+    Measure { unit: Unit::Degrees, ..measure }
+}
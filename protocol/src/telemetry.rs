@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+use crate::flags::{SessionFlags, SessionState};
+
+/// Names of the fields of `TelemetryData` holding a value per car, indexed by car index.
+pub const PER_CAR_FIELDS: [&str; 8] = [
+    "car_class_positions",
+    "car_positions",
+    "car_gears",
+    "car_rpms",
+    "car_steers",
+    "car_laps",
+    "car_laps_perc",
+    "car_pits"
+];
+
+/// Check whether a telemetry field holds a value per car.
+///
+/// Other fields may still be arrays, e.g. `flags_decoded`.
+pub fn is_per_car(field: &str) -> bool {
+    PER_CAR_FIELDS.contains(&field)
+}
+
+///
+/// Live telemetry of the session and every car in it.
+///
+/// Per-car fields are indexed by car index, as in the drivers of the session details.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "actix", derive(actix::Message), rtype(result = "()"))]
+pub struct TelemetryData {
+    pub air_temperature: f32,
+    pub state: i32,
+    pub flags: u32,
+    pub session_number: i32,
+    pub time_remaining: f64,
+    pub track_temperature: f32,
+    pub car_class_positions: Vec<i32>,
+    pub car_positions: Vec<i32>,
+    pub car_gears: Vec<i32>,
+    pub car_rpms: Vec<f32>,
+    pub car_steers: Vec<f32>,
+    pub car_laps: Vec<i32>,
+    pub car_laps_perc: Vec<f32>,
+    pub car_pits: Vec<bool>,
+
+    /// `flags` decoded into the names of the flags which are set. Filled in by `fill_decoded`.
+    #[serde(default, skip_deserializing)]
+    pub flags_decoded: SessionFlags,
+
+    /// `state` decoded into its name. Filled in by `fill_decoded`.
+    #[serde(default, skip_deserializing)]
+    pub state_decoded: SessionState
+}
+
+impl TelemetryData {
+    /// Fill in the decoded forms of the raw `flags` and `state`.
+    pub fn fill_decoded(&mut self) {
+        self.flags_decoded = SessionFlags::from_bits_truncate(self.flags);
+        self.state_decoded = SessionState::from(self.state);
+    }
+}
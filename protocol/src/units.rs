@@ -0,0 +1,109 @@
+//! Values which iRacing sends as text with a unit suffix, such as `"5.79 km"` or `"26.3 C"`.
+
+use serde::{Serialize, Deserialize};
+
+/// Units used by the values in iRacing's session details.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Unit {
+    #[serde(rename = "km")]
+    Kilometres,
+
+    #[serde(rename = "m")]
+    Metres,
+
+    #[serde(rename = "deg")]
+    Degrees,
+
+    #[serde(rename = "rad")]
+    Radians,
+
+    #[serde(rename = "km/h")]
+    KilometresPerHour,
+
+    #[serde(rename = "m/s")]
+    MetresPerSecond,
+
+    #[serde(rename = "C")]
+    Celsius,
+
+    #[serde(rename = "Hg")]
+    InchesOfMercury,
+
+    #[serde(rename = "%")]
+    Percent,
+
+    #[serde(rename = "kg")]
+    Kilograms
+}
+
+/// A number with its unit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Measure {
+    pub value: f64,
+    pub unit: Unit
+}
+
+///
+/// A value with a unit, as sent by iRacing.
+///
+/// The original text is kept and serialized as-is, so the session details are passed on unchanged.
+/// Text which can't be parsed is kept without a value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct Quantity {
+    raw: String,
+    measure: Option<Measure>
+}
+
+impl Unit {
+    fn parse(symbol: &str) -> Option<Self> {
+        match symbol {
+            "km" => Some(Unit::Kilometres),
+            "m" => Some(Unit::Metres),
+            "deg" => Some(Unit::Degrees),
+            "rad" => Some(Unit::Radians),
+            "kph" | "km/h" => Some(Unit::KilometresPerHour),
+            "m/s" => Some(Unit::MetresPerSecond),
+            "C" => Some(Unit::Celsius),
+            "Hg" => Some(Unit::InchesOfMercury),
+            "%" => Some(Unit::Percent),
+            "kg" => Some(Unit::Kilograms),
+            _ => None
+        }
+    }
+}
+
+impl Quantity {
+    /// The text as sent by iRacing.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The parsed value and unit, `None` if the text couldn't be parsed.
+    pub fn measure(&self) -> Option<Measure> {
+        self.measure
+    }
+}
+
+impl From<String> for Quantity {
+    fn from(raw: String) -> Self {
+        let mut parts = raw.split_whitespace();
+
+        let measure = match (parts.next(), parts.next(), parts.next()) {
+            (Some(value), Some(unit), None) => match (value.parse::<f64>(), Unit::parse(unit)) {
+                (Ok(value), Some(unit)) => Some(Measure { value, unit }),
+                _ => None
+            },
+
+            _ => None
+        };
+
+        Self { raw, measure }
+    }
+}
+
+impl From<Quantity> for String {
+    fn from(quantity: Quantity) -> Self {
+        quantity.raw
+    }
+}
@@ -0,0 +1,158 @@
+use iracing_websocket_protocol::{self as protocol, Frame, SessionDetails, SessionFlags, SessionState, TelemetryData};
+use iracing_websocket_protocol::envelope::{self, Hello, Message};
+use iracing_websocket_protocol::units::{Measure, Unit};
+
+fn telemetry() -> TelemetryData {
+    let mut telem = TelemetryData {
+        air_temperature: 25.56,
+        state: 4,
+        flags: 0x0000_0024,
+        session_number: 2,
+        time_remaining: 1234.5,
+        track_temperature: 41.2,
+        car_class_positions: vec![0, 1, 2],
+        car_positions: vec![0, 2, 1],
+        car_gears: vec![0, 4, -1],
+        car_rpms: vec![0.0, 6812.5, 900.0],
+        car_steers: vec![0.0, -0.25, 0.0],
+        car_laps: vec![-1, 12, 12],
+        car_laps_perc: vec![-1.0, 0.4375, 0.5],
+        car_pits: vec![false, false, true],
+        ..TelemetryData::default()
+    };
+
+    telem.fill_decoded();
+    telem
+}
+
+fn session() -> SessionDetails {
+    serde_json::from_str(include_str!("session.json")).expect("Fixture should be a valid session")
+}
+
+#[test]
+fn telemetry_round_trip() {
+    let frame = Frame::Telemetry(telemetry());
+    let text = protocol::encode(&frame).unwrap();
+
+    assert!(text.starts_with('T'));
+    assert_eq!(protocol::decode(&text).unwrap(), frame);
+}
+
+#[test]
+fn session_round_trip() {
+    let frame = Frame::Session(session());
+    let text = protocol::encode(&frame).unwrap();
+
+    assert!(text.starts_with('S'));
+    assert_eq!(protocol::decode(&text).unwrap(), frame);
+}
+
+#[test]
+fn decode_fills_flags_and_state() {
+    let text = protocol::encode(&Frame::Telemetry(telemetry())).unwrap();
+
+    match protocol::decode(&text).unwrap() {
+        Frame::Telemetry(telem) => {
+            assert_eq!(telem.flags_decoded, SessionFlags::GREEN | SessionFlags::BLUE);
+            assert_eq!(telem.state_decoded, SessionState::Racing);
+        },
+
+        frame => panic!("Expected telemetry, got {:?}", frame)
+    }
+}
+
+#[test]
+fn decoded_flags_are_serialized_by_name() {
+    let json = serde_json::to_value(telemetry()).unwrap();
+
+    assert_eq!(json["flags"], 0x24);
+    assert_eq!(json["flags_decoded"], serde_json::json!(["green", "blue"]));
+    assert_eq!(json["state_decoded"], "racing");
+}
+
+#[test]
+fn quantities_keep_their_text() {
+    let session = session();
+    let json = serde_json::to_value(&session).unwrap();
+
+    assert_eq!(json["WeekendInfo"]["TrackLength"], "5.79 km");
+    assert_eq!(session.weekend.track_length.measure(), Some(Measure { value: 5.79, unit: Unit::Kilometres }));
+    assert_eq!(session.weekend.track_pit_speed_limit.measure(), Some(Measure { value: 72.42, unit: Unit::KilometresPerHour }));
+    assert_eq!(session.weekend.options.wind_direction, "N");
+
+    let conditions = session.weekend.conditions();
+    assert_eq!(json["WeekendInfo"]["TrackLatitude"], "27.451036 m");
+    assert_eq!(conditions.track_latitude, Some(Measure { value: 27.451036, unit: Unit::Degrees }));
+    assert_eq!(conditions.track_longitude, Some(Measure { value: -81.353318, unit: Unit::Degrees }));
+}
+
+#[test]
+fn invalid_frames_are_rejected() {
+    assert!(matches!(protocol::decode(""), Err(protocol::Error::Empty)));
+    assert!(matches!(protocol::decode("X{}"), Err(protocol::Error::UnknownType('X'))));
+    assert!(matches!(protocol::decode("T{"), Err(protocol::Error::Invalid('T', _))));
+    assert!(matches!(protocol::decode("S{}"), Err(protocol::Error::Invalid('S', _))));
+}
+
+#[test]
+fn envelope_round_trip() {
+    let hello = Hello {
+        name: "exporter".to_owned(),
+        version: "0.1.0".to_owned(),
+        protocol_version: envelope::PROTOCOL_VERSION,
+        encodings: vec!["json".to_owned()]
+    };
+
+    for msg in [Message::Hello(hello.clone()), Message::Welcome(hello), Message::Telemetry(telemetry()), Message::Session(session())] {
+        let text = envelope::encode(&msg, 7, 1_600_000_000_000).unwrap();
+        let decoded = envelope::decode(&text).unwrap();
+
+        assert!(envelope::is_envelope(&text));
+        assert_eq!(decoded.version, envelope::PROTOCOL_VERSION);
+        assert_eq!(decoded.seq, 7);
+        assert_eq!(decoded.timestamp, 1_600_000_000_000);
+        assert_eq!(decoded.data, msg);
+    }
+}
+
+#[test]
+fn envelope_versions_are_checked() {
+    let text = r#"{"version": 99, "type": "telemetry", "seq": 0, "timestamp": 0, "data": {}}"#;
+
+    assert!(matches!(envelope::decode(text), Err(protocol::Error::UnsupportedVersion(99))));
+}
+
+#[test]
+fn handshakes_decode_at_any_version() {
+    let text = r#"{"version": 99, "type": "hello", "seq": 0, "timestamp": 0,
+        "data": {"name": "future", "version": "9.0.0", "protocol_version": 99, "encodings": ["json"]}}"#;
+
+    match envelope::decode(text).map(|e| e.data) {
+        Ok(Message::Hello(hello)) => assert_eq!(hello.protocol_version, 99),
+        other => panic!("Expected a hello, got {:?}", other)
+    }
+}
+
+#[test]
+fn legacy_frames_are_not_envelopes() {
+    let text = protocol::encode(&Frame::Telemetry(telemetry())).unwrap();
+
+    assert!(!envelope::is_envelope(&text));
+}
+
+#[test]
+fn pace_car_is_recognised_without_its_flag() {
+    // The iRacing session from the exporter doesn't carry `CarIsPaceCar`.
+    let mut value: serde_json::Value = serde_json::from_str(include_str!("session.json")).unwrap();
+
+    for driver in value["DriverInfo"]["Drivers"].as_array_mut().unwrap() {
+        driver.as_object_mut().unwrap().remove("CarIsPaceCar");
+    }
+
+    let session: SessionDetails = serde_json::from_value(value).unwrap();
+    let drivers = &session.drivers.other_drivers;
+
+    assert_eq!(drivers[0].car_is_pace_car, 0);
+    assert!(drivers[0].is_pace_car());
+    assert!(!drivers[1].is_pace_car());
+}
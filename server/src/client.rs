@@ -1,31 +1,92 @@
 //! TelemetryClient is an actor which represents a telemetry receipient connection
-use crate::server;
+use crate::server::{self, Stamp};
+use crate::subscription::Subscription;
+use crate::delta::{self, DeltaEncoder};
+use crate::settings::Heartbeat;
+use crate::metrics::{self, Metrics};
+use protocol::TelemetryData;
+use protocol::envelope::{self, Envelope, Hello};
 
 use std::time::{Instant, Duration};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 
 use actix::prelude::*;
 use actix_web_actors::ws;
+use serde::{Serialize,Deserialize};
 use serde_json::to_string as json;
 
 pub struct WsTelemetryClient {
     hb: Instant,
+    heartbeat: Heartbeat,
     id: usize,
+    metrics: Arc<Metrics>,
+    encoding: Encoding,
+    enveloped: bool,
+    subscription: Subscription,
+    delta: Option<DeltaEncoder>,
+    min_interval: Option<Duration>,
+    last_telemetry: Option<Instant>,
+    pending_telemetry: Option<(Stamp, Arc<TelemetryData>)>,
     server: Addr<server::TelemetryServer>
 }
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Commands a viewer may send as JSON text frames.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Introduce the viewer, switching to enveloped frames if it speaks the server's protocol version.
+    ///
+    /// An alias of sending a `hello` envelope, as sources do.
+    Hello(Hello),
+
+    /// Only receive the given telemetry fields and/or cars.
+    Subscribe(Subscription),
+
+    /// Receive all telemetry fields and cars again.
+    Unsubscribe,
+
+    /// Switch between full telemetry frames and delta frames.
+    Delta {
+        enabled: bool,
+        keyframe_interval: Option<u64>
+    },
+
+    /// Make the next delta frame a keyframe, e.g. after a gap in sequence numbers.
+    Keyframe,
+
+    /// Limit how often telemetry is sent. `None` or zero removes the limit.
+    Rate {
+        max_hz: Option<f64>
+    }
+}
+
+/// Encoding of the frames sent to a viewer.
+///
+/// JSON is sent as text frames, MessagePack as binary frames. Both carry the same `(type, payload)` tuple.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Json,
+    MsgPack
+}
+
+/// WebSocket sub-protocols a viewer may request to choose its encoding.
+pub const PROTOCOLS: [&str; 2] = ["json", "msgpack"];
 
 impl Actor for WsTelemetryClient {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(server::VIEWER_QUEUE_SIZE);
+        self.metrics.viewers.fetch_add(1, Ordering::Relaxed);
         self.hb(ctx);
 
         let addr = ctx.address();
 
         self.server.send(server::Connect {
-            addr: addr.recipient()
+            addr: addr.recipient(),
+            last_seq: None
         }).into_actor(self).then(|res, act, ctx| { 
             match res {
                 Ok(id) => act.id = id,
@@ -42,23 +103,46 @@ impl Actor for WsTelemetryClient {
         });
         Running::Stop
     }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.metrics.viewers.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
-impl Handler<server::Message> for WsTelemetryClient {
+impl Handler<server::Update> for WsTelemetryClient {
     type Result = ();
 
-    fn handle(&mut self, msg: server::Message, ctx: &mut Self::Context) -> Self::Result {
-        match msg {
-            server::Message::Telemetry(telem) => {
-                let data = ('T', telem);
+    fn handle(&mut self, update: server::Update, ctx: &mut Self::Context) -> Self::Result {
+        let stamp = update.stamp;
 
-                ctx.text(json(&data).unwrap());
+        match update.message {
+            server::Message::Telemetry(telem) => {
+                self.queue_telemetry(stamp, telem, ctx);
             },
 
             server::Message::Session(session) => {
-                let data = ('S', session);
+                self.send('S', &*session, stamp, ctx);
+            },
+
+            server::Message::Timing(timing) => {
+                self.send('L', &*timing, stamp, ctx);
+            },
+
+            server::Message::Standings(standings) => {
+                self.send('P', &*standings, stamp, ctx);
+            },
+
+            server::Message::Event(event) => {
+                self.send('E', &*event, stamp, ctx);
+            },
+
+            server::Message::Source(state) => {
+                self.send('F', state, stamp, ctx);
+            },
 
-                ctx.text(json(&data).unwrap());
+            server::Message::Close(reason) => {
+                ctx.close(Some((ws::CloseCode::Policy, reason).into()));
+                ctx.stop();
             }
         };
     }
@@ -85,23 +169,175 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsTelemetryClient
                 ctx.stop();
             }
 
+            ws::Message::Text(txt) => {
+                // Viewers introduce themselves with the same enveloped hello as sources, anything else is a command.
+                if let Ok(envelope::Message::Hello(hello)) = envelope::decode(&txt).map(|e| e.data) {
+                    self.command(Command::Hello(hello), ctx);
+                    return;
+                }
+
+                match serde_json::from_str::<Command>(&txt) {
+                    Ok(cmd) => self.command(cmd, ctx),
+                    Err(e) => warn!("Invalid command from client {}: {}", self.id, e)
+                }
+            }
+
             _ => (),
         }
     }
 }
 
 impl WsTelemetryClient {
-    pub fn new(server_addr: Addr<server::TelemetryServer>) -> Self {
+    pub fn new(server_addr: Addr<server::TelemetryServer>, metrics: Arc<Metrics>, heartbeat: Heartbeat, encoding: Encoding, max_hz: Option<f64>) -> Self {
         Self {
             hb: Instant::now(),
+            heartbeat: heartbeat,
             id: 0,
+            metrics: metrics,
+            encoding: encoding,
+            enveloped: false,
+            subscription: Subscription::default(),
+            delta: None,
+            min_interval: min_interval(max_hz),
+            last_telemetry: None,
+            pending_telemetry: None,
             server: server_addr
         }
     }
 
+    fn command(&mut self, cmd: Command, ctx: &mut ws::WebsocketContext<Self>) {
+        debug!("Client {} command: {:?}", self.id, cmd);
+
+        match cmd {
+            Command::Hello(hello) => self.hello(hello, ctx),
+
+            Command::Subscribe(subscription) => self.subscription = subscription,
+            Command::Unsubscribe => self.subscription = Subscription::default(),
+
+            Command::Delta { enabled, keyframe_interval } => {
+                self.delta = if enabled {
+                    Some(DeltaEncoder::new(keyframe_interval.unwrap_or(delta::DEFAULT_KEYFRAME_INTERVAL)))
+                } else {
+                    None
+                };
+            },
+
+            Command::Keyframe => (),
+
+            Command::Rate { max_hz } => self.min_interval = min_interval(max_hz)
+        }
+
+        // Any change in what is being sent invalidates the viewer's previous frame.
+        if let Some(encoder) = &mut self.delta {
+            encoder.request_keyframe();
+        }
+    }
+
+    /// Answer a viewer's hello, and re-send the room's state in the format the viewer now receives.
+    fn hello(&mut self, hello: Hello, ctx: &mut ws::WebsocketContext<Self>) {
+        info!("Client {} is {} {} (protocol version {})", self.id, hello.name, hello.version, hello.protocol_version);
+
+        let welcome = Hello::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), &PROTOCOLS);
+
+        self.encode(&Envelope::new(envelope::WELCOME, 0, metrics::now_millis(), welcome), ctx);
+
+        // Viewers which speak another version keep receiving legacy frames, and can tell from the welcome.
+        self.enveloped = hello.protocol_version == envelope::PROTOCOL_VERSION;
+        self.server.do_send(server::Refresh { id: self.id });
+    }
+
+    /// Send telemetry now if the viewer's rate limit allows, otherwise hold the latest frame until it does.
+    fn queue_telemetry(&mut self, stamp: Stamp, telem: Arc<TelemetryData>, ctx: &mut ws::WebsocketContext<Self>) {
+        let wait = match (self.min_interval, self.last_telemetry) {
+            (Some(interval), Some(last)) => interval.checked_sub(last.elapsed()),
+            _ => None
+        };
+
+        match wait {
+            None => {
+                self.last_telemetry = Some(Instant::now());
+                self.send_telemetry(stamp, &telem, ctx);
+            },
+
+            Some(wait) => {
+                // Only the newest frame is kept, a flush is already scheduled if one was pending.
+                if self.pending_telemetry.replace((stamp, telem)).is_none() {
+                    ctx.run_later(wait, |act, ctx| act.flush_telemetry(ctx));
+                }
+            }
+        }
+    }
+
+    fn flush_telemetry(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some((stamp, telem)) = self.pending_telemetry.take() {
+            self.last_telemetry = Some(Instant::now());
+            self.send_telemetry(stamp, &telem, ctx);
+        }
+    }
+
+    /// Send telemetry trimmed to the viewer's subscription, as a full or delta frame.
+    fn send_telemetry(&mut self, stamp: Stamp, telem: &TelemetryData, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.delta.is_none() {
+            if self.subscription.is_full() {
+                self.send('T', telem, stamp, ctx);
+            } else {
+                self.send('T', self.subscription.apply(telem), stamp, ctx);
+            }
+
+            return;
+        }
+
+        let current = if self.subscription.is_full() {
+            serde_json::to_value(telem).unwrap_or_default()
+        } else {
+            self.subscription.apply(telem)
+        };
+
+        let frame = match &mut self.delta {
+            Some(encoder) => encoder.encode(current),
+            None => return
+        };
+
+        match frame {
+            delta::Frame::Keyframe(key) => self.send('K', key, stamp, ctx),
+            delta::Frame::Delta(changes) => self.send('D', changes, stamp, ctx)
+        }
+    }
+
+    ///
+    /// Send a frame of the given type, as a `(type, payload)` tuple or in an envelope.
+    ///
+    /// Envelopes name the type in full, e.g. `telemetry` rather than `T`.
+    fn send<T: Serialize>(&self, kind: char, data: T, stamp: Stamp, ctx: &mut ws::WebsocketContext<Self>) {
+        if !self.enveloped {
+            self.encode(&(kind, data), ctx);
+            return;
+        }
+
+        match envelope::type_name(kind) {
+            Some(name) => self.encode(&Envelope::new(name, stamp.seq, stamp.timestamp, data), ctx),
+            None => error!("No envelope type for '{}' frames", kind)
+        }
+    }
+
+    /// Encode and send a frame to the viewer in its chosen encoding.
+    fn encode<T: Serialize>(&self, data: &T, ctx: &mut ws::WebsocketContext<Self>) {
+        match self.encoding {
+            Encoding::Json => match json(data) {
+                Ok(text) => ctx.text(text),
+                Err(e) => error!("Unable to encode frame as JSON: {}", e)
+            },
+
+            Encoding::MsgPack => match rmp_serde::to_vec_named(data) {
+                Ok(bin) => ctx.binary(bin),
+                Err(e) => error!("Unable to encode frame as MessagePack: {}", e)
+            }
+        }
+    }
+
     fn hb(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.heartbeat.timeout {
                 ctx.stop();
                 return;
             }
@@ -109,4 +345,58 @@ impl WsTelemetryClient {
             ctx.ping(b"PING");
         });
     }
-}
\ No newline at end of file
+}
+
+/// The slowest rate a viewer may ask for, so the interval between updates fits in a `Duration`.
+const MIN_RATE: f64 = 0.001;
+
+/// Convert a maximum update rate into the minimum interval between updates.
+fn min_interval(max_hz: Option<f64>) -> Option<Duration> {
+    match max_hz {
+        Some(hz) if hz > 0f64 => Some(Duration::from_secs_f64(1f64 / hz.max(MIN_RATE))),
+        _ => None
+    }
+}
+
+impl Encoding {
+    /// Pick the encoding from the first recognised protocol in a `Sec-WebSocket-Protocol` header.
+    pub fn from_protocols(protocols: &str) -> Option<Self> {
+        protocols.split(',')
+            .map(|p| p.trim())
+            .filter_map(|p| match p {
+                "json" => Some(Encoding::Json),
+                "msgpack" => Some(Encoding::MsgPack),
+                _ => None
+            })
+            .next()
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Json
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_becomes_an_interval() {
+        assert_eq!(min_interval(Some(10f64)), Some(Duration::from_millis(100)));
+        assert_eq!(min_interval(Some(f64::INFINITY)), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn no_rate_is_no_limit() {
+        assert_eq!(min_interval(None), None);
+        assert_eq!(min_interval(Some(0f64)), None);
+        assert_eq!(min_interval(Some(-1f64)), None);
+        assert_eq!(min_interval(Some(f64::NAN)), None);
+    }
+
+    #[test]
+    fn tiny_rates_are_clamped() {
+        assert_eq!(min_interval(Some(1e-300)), Some(Duration::from_secs(1000)));
+    }
+}
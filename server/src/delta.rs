@@ -0,0 +1,217 @@
+//! Delta encoding of telemetry frames.
+//!
+//! A full keyframe is sent periodically, with only the changed fields and car indices sent
+//! in between. Every frame carries a sequence number so viewers can detect missed frames
+//! and request a new keyframe.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use protocol::telemetry;
+
+/// Number of frames between keyframes unless a viewer asks for another interval.
+pub const DEFAULT_KEYFRAME_INTERVAL: u64 = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Keyframe {
+    pub seq: u64,
+    pub data: Value
+}
+
+///
+/// Changes since the previous frame.
+///
+/// Other fields which changed hold their new value. Per-car fields hold an object
+/// mapping each changed car index to its new value.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delta {
+    pub seq: u64,
+    pub changes: Map<String, Value>
+}
+
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Keyframe(Keyframe),
+    Delta(Delta)
+}
+
+#[derive(Debug, Clone)]
+pub struct DeltaEncoder {
+    keyframe_interval: u64,
+    seq: u64,
+    since_keyframe: u64,
+    previous: Option<Map<String, Value>>
+}
+
+impl DeltaEncoder {
+    pub fn new(keyframe_interval: u64) -> Self {
+        Self {
+            keyframe_interval: keyframe_interval.max(1),
+            seq: 0,
+            since_keyframe: 0,
+            previous: None
+        }
+    }
+
+    /// Make the next frame a keyframe.
+    pub fn request_keyframe(&mut self) {
+        self.previous = None;
+    }
+
+    /// Encode the next frame, given the full current state.
+    pub fn encode(&mut self, current: Value) -> Frame {
+        self.seq += 1;
+
+        let current = match current {
+            Value::Object(map) => map,
+            other => return Frame::Keyframe(Keyframe { seq: self.seq, data: other })
+        };
+
+        let previous = match self.previous.take() {
+            Some(p) if self.since_keyframe < self.keyframe_interval => p,
+            _ => {
+                self.since_keyframe = 1;
+                self.previous = Some(current.clone());
+
+                return Frame::Keyframe(Keyframe { seq: self.seq, data: Value::Object(current) });
+            }
+        };
+
+        let changes = diff(&previous, &current);
+
+        self.since_keyframe += 1;
+        self.previous = Some(current);
+
+        Frame::Delta(Delta { seq: self.seq, changes: changes })
+    }
+}
+
+/// Find the fields and car indices which differ between two frames.
+fn diff(previous: &Map<String, Value>, current: &Map<String, Value>) -> Map<String, Value> {
+    let mut changes = Map::new();
+
+    for (name, value) in current {
+        let old = previous.get(name);
+
+        if old == Some(value) {
+            continue;
+        }
+
+        match (old, value) {
+            (Some(Value::Array(old)), Value::Array(new)) if old.len() == new.len() && telemetry::is_per_car(name) => {
+                let cars: Map<String, Value> = old.iter().zip(new.iter())
+                    .enumerate()
+                    .filter(|(_, (o, n))| o != n)
+                    .map(|(idx, (_, n))| (idx.to_string(), n.clone()))
+                    .collect();
+
+                changes.insert(name.clone(), Value::Object(cars));
+            },
+
+            _ => {
+                changes.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn delta(frame: Frame) -> Delta {
+        match frame {
+            Frame::Delta(d) => d,
+            Frame::Keyframe(k) => panic!("Expected a delta, got keyframe {:?}", k)
+        }
+    }
+
+    fn keyframe(frame: Frame) -> Keyframe {
+        match frame {
+            Frame::Keyframe(k) => k,
+            Frame::Delta(d) => panic!("Expected a keyframe, got delta {:?}", d)
+        }
+    }
+
+    #[test]
+    fn first_frame_is_a_keyframe() {
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        let key = keyframe(encoder.encode(json!({"state": 4, "car_laps": [1, 2]})));
+
+        assert_eq!(key.seq, 1);
+        assert_eq!(key.data, json!({"state": 4, "car_laps": [1, 2]}));
+    }
+
+    #[test]
+    fn deltas_hold_changed_fields_and_cars() {
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        encoder.encode(json!({"state": 4, "flags": 0, "car_laps": [1, 2, 3]}));
+
+        let d = delta(encoder.encode(json!({"state": 5, "flags": 0, "car_laps": [1, 3, 3]})));
+
+        assert_eq!(d.seq, 2);
+        assert_eq!(Value::Object(d.changes), json!({"state": 5, "car_laps": {"1": 3}}));
+    }
+
+    #[test]
+    fn unchanged_frames_are_empty_deltas() {
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        encoder.encode(json!({"state": 4, "car_laps": [1, 2]}));
+
+        assert!(delta(encoder.encode(json!({"state": 4, "car_laps": [1, 2]}))).changes.is_empty());
+    }
+
+    #[test]
+    fn resized_and_new_fields_are_sent_whole() {
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        encoder.encode(json!({"car_laps": [1, 2]}));
+
+        let d = delta(encoder.encode(json!({"car_laps": [1, 2, 0], "state": 1})));
+
+        assert_eq!(Value::Object(d.changes), json!({"car_laps": [1, 2, 0], "state": 1}));
+    }
+
+    #[test]
+    fn keyframes_are_sent_at_the_interval() {
+        let mut encoder = DeltaEncoder::new(3);
+
+        keyframe(encoder.encode(json!({"state": 1})));
+        delta(encoder.encode(json!({"state": 2})));
+        delta(encoder.encode(json!({"state": 3})));
+
+        let key = keyframe(encoder.encode(json!({"state": 4})));
+        assert_eq!(key.seq, 4);
+
+        delta(encoder.encode(json!({"state": 5})));
+    }
+
+    #[test]
+    fn keyframes_can_be_requested() {
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        encoder.encode(json!({"state": 1}));
+        encoder.request_keyframe();
+
+        assert_eq!(keyframe(encoder.encode(json!({"state": 1}))).data, json!({"state": 1}));
+    }
+
+    #[test]
+    fn non_objects_are_keyframes() {
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        encoder.encode(json!({"state": 1}));
+
+        assert_eq!(keyframe(encoder.encode(Value::Null)).data, Value::Null);
+    }
+
+    #[test]
+    fn session_arrays_are_sent_whole() {
+        let mut encoder = DeltaEncoder::new(DEFAULT_KEYFRAME_INTERVAL);
+        encoder.encode(json!({"flags_decoded": ["white", "green"], "car_laps": [1, 2]}));
+
+        let d = delta(encoder.encode(json!({"flags_decoded": ["white", "yellow"], "car_laps": [1, 2]})));
+
+        assert_eq!(Value::Object(d.changes), json!({"flags_decoded": ["white", "yellow"]}));
+    }
+}
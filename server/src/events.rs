@@ -0,0 +1,349 @@
+//! Race events, detected by comparing consecutive telemetry frames and session updates.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+
+use protocol::{TelemetryData, SessionDetails};
+
+/// Number of events kept in the log of each room.
+const EVENT_LOG_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Sequence number of the event within its room.
+    pub id: u64,
+
+    /// Time the data which raised the event was received (ms since the UNIX epoch).
+    pub time: u64,
+
+    #[serde(flatten)]
+    pub kind: EventKind
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    PitEntry { car_idx: usize },
+    PitExit { car_idx: usize },
+
+    /// `car_idx` moved up to `position`, ahead of `passed_car_idx`.
+    Overtake { car_idx: usize, passed_car_idx: usize, position: i32 },
+
+    LapCompleted { car_idx: usize, lap: i32 },
+    SessionState { from: i32, to: i32 },
+    Flags { from: u32, to: u32 },
+    SessionNumber { from: i32, to: i32 },
+    DriverJoined { car_idx: usize, driver_name: String },
+    DriverLeft { car_idx: usize, driver_name: String }
+}
+
+/// Filters for querying the event log.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventQuery {
+    /// Only return events after this id.
+    pub since: Option<u64>,
+
+    /// Only return events involving this car.
+    pub car_idx: Option<usize>
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EventDetector {
+    next_id: u64,
+    telemetry: Option<Arc<TelemetryData>>,
+
+    /// Driver name in each car, `None` until the first session details are received.
+    drivers: Option<BTreeMap<usize, String>>,
+
+    log: VecDeque<Event>
+}
+
+impl EventKind {
+    /// Check whether the event involves a car.
+    pub fn involves(&self, car: usize) -> bool {
+        match *self {
+            EventKind::PitEntry { car_idx }
+                | EventKind::PitExit { car_idx }
+                | EventKind::LapCompleted { car_idx, .. }
+                | EventKind::DriverJoined { car_idx, .. }
+                | EventKind::DriverLeft { car_idx, .. } => car_idx == car,
+
+            EventKind::Overtake { car_idx, passed_car_idx, .. } => car_idx == car || passed_car_idx == car,
+
+            _ => false
+        }
+    }
+}
+
+impl EventDetector {
+    /// Compare telemetry received at `received` (ms) with the previous frame.
+    pub fn telemetry(&mut self, telem: &Arc<TelemetryData>, received: u64) -> Vec<Event> {
+        let prev = match self.telemetry.replace(telem.clone()) {
+            Some(p) => p,
+            None => return Vec::new()
+        };
+
+        let mut kinds = Vec::new();
+
+        if prev.state != telem.state {
+            kinds.push(EventKind::SessionState { from: prev.state, to: telem.state });
+        }
+
+        if prev.flags != telem.flags {
+            kinds.push(EventKind::Flags { from: prev.flags, to: telem.flags });
+        }
+
+        if prev.session_number != telem.session_number {
+            // Cars' laps, positions and pit status all reset with a new session, so aren't compared.
+            kinds.push(EventKind::SessionNumber { from: prev.session_number, to: telem.session_number });
+        } else {
+            cars(&prev, telem, &mut kinds);
+        }
+
+        self.log_all(kinds, received)
+    }
+
+    /// Compare session details received at `received` (ms) with the previous update.
+    pub fn session(&mut self, session: &SessionDetails, received: u64) -> Vec<Event> {
+        let current: BTreeMap<usize, String> = session.drivers.other_drivers.iter()
+            .filter(|d| d.is_spectator == 0 && !d.is_pace_car())
+            .map(|d| (d.index, d.user_name.clone()))
+            .collect();
+
+        let prev = match self.drivers.replace(current.clone()) {
+            Some(p) => p,
+            None => return Vec::new()
+        };
+
+        let mut kinds = Vec::new();
+
+        for (&idx, name) in &prev {
+            if current.get(&idx) != Some(name) {
+                kinds.push(EventKind::DriverLeft { car_idx: idx, driver_name: name.clone() });
+            }
+        }
+
+        for (&idx, name) in &current {
+            if prev.get(&idx) != Some(name) {
+                kinds.push(EventKind::DriverJoined { car_idx: idx, driver_name: name.clone() });
+            }
+        }
+
+        self.log_all(kinds, received)
+    }
+
+    /// Get logged events matching a query, oldest first.
+    pub fn query(&self, query: &EventQuery) -> Vec<Event> {
+        self.log.iter()
+            .filter(|e| query.since.map_or(true, |since| e.id > since))
+            .filter(|e| query.car_idx.map_or(true, |car| e.kind.involves(car)))
+            .cloned()
+            .collect()
+    }
+
+    fn log_all(&mut self, kinds: Vec<EventKind>, received: u64) -> Vec<Event> {
+        let mut events = Vec::with_capacity(kinds.len());
+
+        for kind in kinds {
+            self.next_id += 1;
+
+            let event = Event { id: self.next_id, time: received, kind: kind };
+
+            if self.log.len() >= EVENT_LOG_SIZE {
+                self.log.pop_front();
+            }
+
+            self.log.push_back(event.clone());
+            events.push(event);
+        }
+
+        events
+    }
+}
+
+/// Compare the per-car fields of two frames from the same session.
+fn cars(prev: &TelemetryData, telem: &TelemetryData, kinds: &mut Vec<EventKind>) {
+    for (idx, (&was, &is)) in prev.car_pits.iter().zip(&telem.car_pits).enumerate() {
+        if !was && is {
+            kinds.push(EventKind::PitEntry { car_idx: idx });
+        } else if was && !is {
+            kinds.push(EventKind::PitExit { car_idx: idx });
+        }
+    }
+
+    for (idx, (&was, &is)) in prev.car_laps.iter().zip(&telem.car_laps).enumerate() {
+        // iRacing counts the lap a car is on, so the lap it was on has been completed.
+        if was > 0 && is > was {
+            kinds.push(EventKind::LapCompleted { car_idx: idx, lap: was });
+        }
+    }
+
+    let positions = prev.car_positions.iter().zip(&telem.car_positions).enumerate();
+
+    for (idx, (&was, &is)) in positions {
+        if was <= 0 || is <= 0 || is >= was {
+            continue;
+        }
+
+        // The car which held the position before, and has since dropped behind.
+        let passed = prev.car_positions.iter().enumerate()
+            .find(|&(other, &pos)| other != idx && pos == is && telem.car_positions.get(other).map_or(false, |&now| now > is));
+
+        if let Some((other, _)) = passed {
+            kinds.push(EventKind::Overtake { car_idx: idx, passed_car_idx: other, position: is });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::session;
+
+    fn telemetry(laps: &[i32], positions: &[i32], pits: &[bool]) -> Arc<TelemetryData> {
+        Arc::new(TelemetryData {
+            session_number: 1,
+            car_laps: laps.to_vec(),
+            car_positions: positions.to_vec(),
+            car_pits: pits.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn kinds(events: Vec<Event>) -> Vec<EventKind> {
+        events.into_iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn first_telemetry_raises_nothing() {
+        let mut detector = EventDetector::default();
+
+        assert!(detector.telemetry(&telemetry(&[1, 1], &[1, 2], &[false, true]), 1000).is_empty());
+    }
+
+    #[test]
+    fn pit_stops_and_laps_are_detected() {
+        let mut detector = EventDetector::default();
+        detector.telemetry(&telemetry(&[1, 0], &[1, 2], &[false, false]), 1000);
+
+        let entry = kinds(detector.telemetry(&telemetry(&[2, 1], &[1, 2], &[true, false]), 2000));
+        let exit = kinds(detector.telemetry(&telemetry(&[2, 1], &[1, 2], &[false, false]), 3000));
+
+        // Car 1 starting its first lap doesn't complete one.
+        assert_eq!(entry.len(), 2);
+        assert!(matches!(entry[0], EventKind::PitEntry { car_idx: 0 }));
+        assert!(matches!(entry[1], EventKind::LapCompleted { car_idx: 0, lap: 1 }));
+
+        assert_eq!(exit.len(), 1);
+        assert!(matches!(exit[0], EventKind::PitExit { car_idx: 0 }));
+    }
+
+    #[test]
+    fn overtakes_name_the_passed_car() {
+        let mut detector = EventDetector::default();
+        detector.telemetry(&telemetry(&[], &[1, 2, 3], &[]), 1000);
+
+        let events = kinds(detector.telemetry(&telemetry(&[], &[1, 3, 2], &[]), 2000));
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], EventKind::Overtake { car_idx: 2, passed_car_idx: 1, position: 2 }));
+    }
+
+    #[test]
+    fn cars_gaining_a_position_without_passing_are_not_overtakes() {
+        let mut detector = EventDetector::default();
+        detector.telemetry(&telemetry(&[], &[0, 2], &[]), 1000);
+
+        assert!(detector.telemetry(&telemetry(&[], &[0, 1], &[]), 2000).is_empty());
+    }
+
+    #[test]
+    fn session_changes_replace_car_events() {
+        let mut detector = EventDetector::default();
+        detector.telemetry(&telemetry(&[5], &[1], &[true]), 1000);
+
+        let mut next = (*telemetry(&[0], &[0], &[false])).clone();
+        next.session_number = 2;
+        next.state = 4;
+        next.flags = 0x4;
+
+        let events = kinds(detector.telemetry(&Arc::new(next), 2000));
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], EventKind::SessionState { from: 0, to: 4 }));
+        assert!(matches!(events[1], EventKind::Flags { from: 0, to: 0x4 }));
+        assert!(matches!(events[2], EventKind::SessionNumber { from: 1, to: 2 }));
+    }
+
+    #[test]
+    fn drivers_joining_and_leaving_are_detected() {
+        let mut detector = EventDetector::default();
+        let mut session = session();
+
+        assert!(detector.session(&session, 1000).is_empty());
+
+        session.drivers.other_drivers[1].user_name = "Another Driver".to_owned();
+
+        let events = kinds(detector.session(&session, 2000));
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], EventKind::DriverLeft { car_idx: 1, driver_name } if driver_name == "A Driver"));
+        assert!(matches!(&events[1], EventKind::DriverJoined { car_idx: 1, driver_name } if driver_name == "Another Driver"));
+    }
+
+    #[test]
+    fn pace_car_is_not_a_driver() {
+        let mut detector = EventDetector::default();
+        let mut session = session();
+        detector.session(&session, 1000);
+
+        session.drivers.other_drivers[0].user_name = "Safety Car".to_owned();
+
+        assert!(detector.session(&session, 2000).is_empty());
+    }
+
+    #[test]
+    fn log_is_queried_by_id_and_car() {
+        let mut detector = EventDetector::default();
+        detector.telemetry(&telemetry(&[1, 1], &[], &[false, false]), 1000);
+        detector.telemetry(&telemetry(&[1, 1], &[], &[true, false]), 2000);
+        detector.telemetry(&telemetry(&[1, 1], &[], &[true, true]), 3000);
+        detector.telemetry(&telemetry(&[1, 1], &[], &[false, true]), 4000);
+
+        let all = detector.query(&EventQuery::default());
+        let ids: Vec<u64> = all.iter().map(|e| e.id).collect();
+
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(all[1].time, 3000);
+
+        assert_eq!(detector.query(&EventQuery { since: Some(1), car_idx: None }).len(), 2);
+        assert_eq!(detector.query(&EventQuery { since: None, car_idx: Some(1) }).len(), 1);
+        assert_eq!(detector.query(&EventQuery { since: Some(2), car_idx: Some(0) }).len(), 1);
+    }
+
+    #[test]
+    fn log_keeps_the_latest_events() {
+        let mut detector = EventDetector::default();
+
+        for i in 0..(EVENT_LOG_SIZE as u32 + 11) {
+            let mut telem = (*telemetry(&[], &[], &[])).clone();
+            telem.flags = i;
+            detector.telemetry(&Arc::new(telem), 1000);
+        }
+
+        let log = detector.query(&EventQuery::default());
+
+        assert_eq!(log.len(), EVENT_LOG_SIZE);
+        assert_eq!(log[0].id, 11);
+    }
+
+    #[test]
+    fn events_serialize_flat() {
+        let event = Event { id: 42, time: 1000, kind: EventKind::Overtake { car_idx: 4, passed_car_idx: 9, position: 3 } };
+
+        assert_eq!(serde_json::to_value(&event).unwrap(), serde_json::json!({
+            "id": 42, "time": 1000, "type": "overtake", "car_idx": 4, "passed_car_idx": 9, "position": 3
+        }));
+    }
+}
@@ -0,0 +1,21 @@
+//! Data shared by the unit tests.
+
+use protocol::SessionDetails;
+
+/// Session details for session 0, with the pace car in slot 0 and "A Driver" in slot 1.
+///
+/// Car 1 has a result for completing lap 3 in 123.0567s in first place.
+pub fn session() -> SessionDetails {
+    serde_json::from_str(include_str!("../tests/fixtures/session.json")).unwrap()
+}
+
+/// The same session as sent by the exporter, whose drivers don't carry `CarIsPaceCar`.
+pub fn session_without_pace_car_flag() -> SessionDetails {
+    let mut value: serde_json::Value = serde_json::from_str(include_str!("../tests/fixtures/session.json")).unwrap();
+
+    for driver in value["DriverInfo"]["Drivers"].as_array_mut().unwrap() {
+        driver.as_object_mut().unwrap().remove("CarIsPaceCar");
+    }
+
+    serde_json::from_value(value).unwrap()
+}
@@ -0,0 +1,377 @@
+//! Lap history of every car in the current session.
+//!
+//! Laps are recorded as cars cross the line, seen as `car_laps` increasing in the telemetry. Lap times and
+//! positions come from the session's results, which iRacing updates shortly after each crossing.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use serde::{Serialize, Deserialize};
+
+use protocol::{TelemetryData, SessionDetails};
+
+/// Laps completed by every car in the session.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LapTable {
+    pub session_number: i32,
+    pub cars: Vec<CarLaps>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CarLaps {
+    pub car_idx: usize,
+    pub laps: Vec<Lap>
+}
+
+/// A completed lap. `time` is in seconds, `None` until the session results include it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Lap {
+    pub lap: i32,
+    pub time: Option<f64>,
+
+    /// Position when the car crossed the line.
+    pub position: Option<i32>,
+
+    /// Whether the car was on pit road at any point during the lap.
+    pub pitted: bool
+}
+
+/// Formats lap history may be fetched in.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LapFormat {
+    Json,
+    Csv
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LapHistory {
+    session_number: Option<i32>,
+    sub_session_id: Option<i32>,
+    cars: BTreeMap<usize, Car>
+}
+
+#[derive(Debug, Clone, Default)]
+struct Car {
+    /// The lap the car was on in the previous telemetry.
+    lap: i32,
+
+    /// Whether the car has been on pit road during the lap it is on.
+    pitted: bool,
+
+    laps: Vec<Lap>,
+
+    /// Lap number, time and position from the results, when they arrive before the lap is seen in telemetry.
+    result: Option<(i32, f64, i32)>
+}
+
+impl LapHistory {
+    ///
+    /// Record the laps completed since the previous telemetry.
+    ///
+    /// Slots without a car report a negative lap, and only cars which have been seen on a lap are kept.
+    pub fn telemetry(&mut self, telem: &TelemetryData) {
+        if self.session_number != Some(telem.session_number) {
+            self.session_number = Some(telem.session_number);
+            self.cars.clear();
+        }
+
+        for (idx, &lap) in telem.car_laps.iter().enumerate() {
+            if lap < 0 {
+                continue;
+            }
+
+            let car = self.cars.entry(idx).or_default();
+            let on_pit_road = telem.car_pits.get(idx).cloned().unwrap_or(false);
+
+            // The session restarted under the same number, e.g. a replay looping, so its later laps are being run again.
+            if lap < car.lap {
+                car.laps.retain(|l| l.lap < lap);
+                car.result = None;
+                car.pitted = false;
+            }
+
+            // iRacing counts the lap a car is on, so the lap it was on has been completed.
+            if car.lap > 0 && lap > car.lap {
+                let position = telem.car_positions.get(idx).cloned().filter(|&p| p > 0);
+
+                car.laps.push(Lap { lap: car.lap, time: None, position: position, pitted: car.pitted });
+                car.pitted = false;
+
+                if let Some((lap, time, position)) = car.result.take() {
+                    car.apply(lap, time, position);
+                }
+            }
+
+            car.lap = lap;
+            car.pitted |= on_pit_road;
+        }
+    }
+
+    /// Fill in lap times and positions from the results of the current session.
+    pub fn session(&mut self, session: &SessionDetails) {
+        let sub_session_id = session.weekend.sub_session_id;
+
+        // A new sub-session may reuse the session numbers of the last.
+        if self.sub_session_id.map_or(false, |id| id != sub_session_id) {
+            self.cars.clear();
+        }
+
+        self.sub_session_id = Some(sub_session_id);
+
+        let number = match self.session_number {
+            Some(n) => n,
+            None => return
+        };
+
+        let results = session.session.sessions.iter()
+            .find(|s| s.session_number == number as u64)
+            .map(|s| &s.results);
+
+        for result in results.into_iter().flatten() {
+            // iRacing reports -1 for laps without a time.
+            if result.car_idx < 0 || result.laps_complete <= 0 || result.last_time <= 0f32 {
+                continue;
+            }
+
+            if let Some(car) = self.cars.get_mut(&(result.car_idx as usize)) {
+                if !car.apply(result.laps_complete, result.last_time as f64, result.position) {
+                    car.result = Some((result.laps_complete, result.last_time as f64, result.position));
+                }
+            }
+        }
+    }
+
+    /// Get the laps of every car which has completed one, or `None` before any telemetry has been received.
+    pub fn table(&self) -> Option<LapTable> {
+        let session_number = self.session_number?;
+
+        let cars = self.cars.iter()
+            .filter(|(_, car)| !car.laps.is_empty())
+            .map(|(&idx, car)| CarLaps { car_idx: idx, laps: car.laps.clone() })
+            .collect();
+
+        Some(LapTable { session_number: session_number, cars: cars })
+    }
+
+    /// Whether any telemetry has been received, so the history covers a session.
+    pub fn started(&self) -> bool {
+        self.session_number.is_some()
+    }
+
+    /// Get the laps of a single car, or `None` if the car isn't in the session.
+    pub fn car(&self, car_idx: usize) -> Option<CarLaps> {
+        self.cars.get(&car_idx).map(|car| CarLaps { car_idx: car_idx, laps: car.laps.clone() })
+    }
+}
+
+impl Car {
+    /// Set the time and position of a completed lap. Returns false if the lap hasn't been seen yet.
+    fn apply(&mut self, lap: i32, time: f64, position: i32) -> bool {
+        match self.laps.iter_mut().rev().find(|l| l.lap == lap) {
+            Some(l) => {
+                l.time = Some(time);
+
+                if position > 0 {
+                    l.position = Some(position);
+                }
+
+                true
+            },
+
+            None => false
+        }
+    }
+}
+
+impl LapFormat {
+    /// Pick the format from an `Accept` header, defaulting to JSON.
+    pub fn from_accept(accept: &str) -> Self {
+        if accept.split(',').any(|t| t.trim().starts_with("text/csv")) {
+            LapFormat::Csv
+        } else {
+            LapFormat::Json
+        }
+    }
+}
+
+/// Write laps as CSV, one row per lap. Missing times and positions are left empty.
+pub fn to_csv<'a, I: IntoIterator<Item = &'a CarLaps>>(cars: I) -> String {
+    let mut out = String::from("car_idx,lap,time,position,pitted\n");
+
+    for car in cars {
+        for lap in &car.laps {
+            let time = lap.time.map(|t| format!("{:.3}", t)).unwrap_or_default();
+            let position = lap.position.map(|p| p.to_string()).unwrap_or_default();
+
+            let _ = writeln!(out, "{},{},{},{},{}", car.car_idx, lap.lap, time, position, lap.pitted);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::session;
+
+    fn telemetry(session_number: i32, laps: &[i32], positions: &[i32], pits: &[bool]) -> TelemetryData {
+        TelemetryData {
+            session_number: session_number,
+            car_laps: laps.to_vec(),
+            car_positions: positions.to_vec(),
+            car_pits: pits.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn laps(history: &LapHistory, car_idx: usize) -> Vec<i32> {
+        history.car(car_idx).unwrap().laps.iter().map(|l| l.lap).collect()
+    }
+
+    #[test]
+    fn nothing_is_known_before_telemetry() {
+        let history = LapHistory::default();
+
+        assert!(!history.started());
+        assert!(history.table().is_none());
+        assert!(history.car(0).is_none());
+    }
+
+    #[test]
+    fn laps_are_recorded_at_the_line() {
+        let mut history = LapHistory::default();
+
+        history.telemetry(&telemetry(0, &[1, 1], &[2, 1], &[false, false]));
+        history.telemetry(&telemetry(0, &[1, 1], &[2, 1], &[true, false]));
+        history.telemetry(&telemetry(0, &[2, 1], &[1, 2], &[false, false]));
+        history.telemetry(&telemetry(0, &[3, 1], &[1, 2], &[false, false]));
+
+        let car = history.car(0).unwrap();
+
+        assert_eq!(car.laps.len(), 2);
+        assert_eq!((car.laps[0].lap, car.laps[0].position, car.laps[0].pitted, car.laps[0].time), (1, Some(1), true, None));
+        assert_eq!((car.laps[1].lap, car.laps[1].position, car.laps[1].pitted), (2, Some(1), false));
+
+        assert!(history.car(1).unwrap().laps.is_empty());
+    }
+
+    #[test]
+    fn starting_the_first_lap_does_not_complete_one() {
+        let mut history = LapHistory::default();
+
+        history.telemetry(&telemetry(0, &[0], &[0], &[false]));
+        history.telemetry(&telemetry(0, &[1], &[1], &[false]));
+
+        assert!(laps(&history, 0).is_empty());
+    }
+
+    #[test]
+    fn empty_slots_are_not_cars() {
+        let mut history = LapHistory::default();
+
+        history.telemetry(&telemetry(0, &[1, -1], &[1, 0], &[false, false]));
+        history.telemetry(&telemetry(0, &[2, -1], &[1, 0], &[false, false]));
+
+        assert!(history.car(1).is_none());
+        assert!(history.car(63).is_none());
+        assert_eq!(history.table().unwrap().cars.len(), 1);
+    }
+
+    #[test]
+    fn results_fill_in_times_and_positions() {
+        let mut history = LapHistory::default();
+
+        history.telemetry(&telemetry(0, &[-1, 3], &[0, 2], &[false, false]));
+        history.telemetry(&telemetry(0, &[-1, 4], &[0, 2], &[false, false]));
+        history.session(&session());
+
+        let lap = &history.car(1).unwrap().laps[0];
+
+        assert_eq!(lap.lap, 3);
+        assert_eq!(lap.time, Some(123.0567f32 as f64));
+        assert_eq!(lap.position, Some(1));
+    }
+
+    #[test]
+    fn results_arriving_first_are_applied_at_the_line() {
+        let mut history = LapHistory::default();
+
+        history.telemetry(&telemetry(0, &[-1, 3], &[0, 2], &[false, false]));
+        history.session(&session());
+        assert!(laps(&history, 1).is_empty());
+
+        history.telemetry(&telemetry(0, &[-1, 4], &[0, 2], &[false, false]));
+
+        assert_eq!(history.car(1).unwrap().laps[0].time, Some(123.0567f32 as f64));
+    }
+
+    #[test]
+    fn a_new_session_number_clears_the_history() {
+        let mut history = LapHistory::default();
+
+        history.telemetry(&telemetry(0, &[1], &[1], &[false]));
+        history.telemetry(&telemetry(0, &[2], &[1], &[false]));
+        history.telemetry(&telemetry(1, &[2], &[1], &[false]));
+
+        assert_eq!(history.table().unwrap().session_number, 1);
+        assert!(laps(&history, 0).is_empty());
+    }
+
+    #[test]
+    fn a_new_sub_session_clears_the_history() {
+        let mut history = LapHistory::default();
+        let mut session = session();
+
+        history.session(&session);
+        history.telemetry(&telemetry(0, &[1], &[1], &[false]));
+        history.telemetry(&telemetry(0, &[2], &[1], &[false]));
+        history.session(&session);
+        assert_eq!(laps(&history, 0), vec![1]);
+
+        session.weekend.sub_session_id += 1;
+        history.session(&session);
+
+        assert!(history.car(0).is_none());
+    }
+
+    #[test]
+    fn rewound_laps_are_dropped() {
+        let mut history = LapHistory::default();
+
+        for lap in 1..=4 {
+            history.telemetry(&telemetry(0, &[lap], &[1], &[false]));
+        }
+
+        assert_eq!(laps(&history, 0), vec![1, 2, 3]);
+
+        // A looping replay starts the session again.
+        for lap in 1..=3 {
+            history.telemetry(&telemetry(0, &[lap], &[1], &[false]));
+        }
+
+        assert_eq!(laps(&history, 0), vec![1, 2]);
+    }
+
+    #[test]
+    fn csv_leaves_missing_values_empty() {
+        let cars = vec![
+            CarLaps { car_idx: 1, laps: vec![
+                Lap { lap: 1, time: Some(92.4167), position: Some(3), pitted: false },
+                Lap { lap: 2, time: None, position: None, pitted: true }
+            ] },
+            CarLaps { car_idx: 4, laps: vec![] }
+        ];
+
+        assert_eq!(to_csv(&cars), "car_idx,lap,time,position,pitted\n1,1,92.417,3,false\n1,2,,,true\n");
+        assert_eq!(to_csv(None), "car_idx,lap,time,position,pitted\n");
+    }
+
+    #[test]
+    fn csv_is_chosen_from_the_accept_header() {
+        assert_eq!(LapFormat::from_accept("text/csv"), LapFormat::Csv);
+        assert_eq!(LapFormat::from_accept("application/json, text/csv;q=0.5"), LapFormat::Csv);
+        assert_eq!(LapFormat::from_accept("*/*"), LapFormat::Json);
+        assert_eq!(LapFormat::from_accept(""), LapFormat::Json);
+    }
+}
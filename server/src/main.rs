@@ -2,64 +2,628 @@ extern crate actix;
 extern crate actix_web_actors;
 #[macro_use] extern crate log;
 extern crate env_logger;
+extern crate config;
 
-use actix_web::Responder;
-use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer, middleware};
+use actix_web::{web, error, App, Error, HttpRequest, HttpResponse, HttpServer, middleware};
+use actix_web::http::header;
 use actix_web_actors::ws;
 use actix::{Actor, Addr};
+use serde::{Serialize,Deserialize};
+use serde_json::to_string as json;
 
 use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
-pub mod session;
 pub mod server;
+pub mod rooms;
+pub mod settings;
+pub mod metrics;
+mod tls;
+mod recorder;
+mod replay;
 mod source;
 mod client;
+mod sse;
+mod subscription;
+mod delta;
+mod timing;
+mod standings;
+mod events;
+mod laps;
+
+#[cfg(test)]
+mod fixtures;
+
+use settings::Settings;
 
 #[derive(Clone)]
 pub struct AppState {
     stream_password: String,
-    pub server_addr: Addr<server::TelemetryServer>
+    admin_password: String,
+    record_dir: PathBuf,
+    replay: Option<Addr<replay::Replay>>,
+    heartbeat: settings::Heartbeat,
+    max_viewers: usize,
+    ready_max_age: Duration,
+    source_stale_timeout: Duration,
+    metrics: Arc<metrics::Metrics>,
+    pub rooms: Addr<rooms::RoomRegistry>
+}
+
+/// Options a viewer may pass as query parameters when connecting.
+#[derive(Deserialize,Debug)]
+pub struct ClientOptions {
+    encoding: Option<client::Encoding>,
+    max_hz: Option<f64>
+}
+
+/// Readiness of the server to stream data to viewers.
+#[derive(Serialize,Debug)]
+pub struct Readiness {
+    ready: bool,
+    sources: usize,
+    telemetry_age: Option<f64>,
+    session_age: Option<f64>
+}
+
+/// Options for fetching lap history.
+#[derive(Deserialize,Debug)]
+pub struct LapOptions {
+    format: Option<laps::LapFormat>
 }
 
 #[actix_rt::main]
 pub async fn main() -> io::Result<()> {
     env_logger::init();
 
+    let settings = match Settings::load() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Invalid Configuration: {:?}", e);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+        }
+    };
+
     info!("Initalizing Telemetry Server");
 
-    let state = AppState::new();
+    if settings.stream_password.is_empty() {
+        warn!("No stream password configured, any client may connect as a source");
+    }
 
-    HttpServer::new(move || {
+    let tls = match (&settings.tls_cert, &settings.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::server_config(cert, key, Duration::from_millis(settings.tls_reload_interval))?),
+        (None, None) => None,
+        _ => {
+            error!("Invalid Configuration: both tls_cert and tls_key must be set to enable TLS");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incomplete TLS configuration"));
+        }
+    };
+
+    let mut state = AppState::new(&settings);
+
+    if let Some(path) = &settings.replay {
+        state.replay = Some(start_replay(&state, &settings, path).await?);
+    }
+
+    let mut server = HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
             .data(state.clone())
             .service(web::resource("/telemetry").to(connect_client))
+            .service(web::resource("/telemetry/{room}").to(connect_client))
+            .service(web::resource("/events").to(stream_events))
+            .service(web::resource("/events/{room}").to(stream_events))
             .service(web::resource("/source").to(connect_source))
+            .service(web::resource("/source/{room}").to(connect_source))
             .service(web::resource("/session").to(get_session))
-    }).bind("0.0.0.0:8088")?.run().await
+            .service(web::resource("/session/{room}").to(get_session))
+            .service(web::resource("/weekend").to(get_conditions))
+            .service(web::resource("/weekend/{room}").to(get_conditions))
+            .service(web::resource("/timing").to(get_timing))
+            .service(web::resource("/timing/{room}").to(get_timing))
+            .service(web::resource("/standings").to(get_standings))
+            .service(web::resource("/standings/{room}").to(get_standings))
+            .service(web::resource("/event-log").to(get_event_log))
+            .service(web::resource("/event-log/{room}").to(get_event_log))
+            .service(web::resource("/laps").to(get_laps))
+            .service(web::resource("/laps/{room}").to(get_laps))
+            .service(web::resource("/cars/{car_idx}/laps").to(get_car_laps))
+            .service(web::resource("/cars/{car_idx}/laps/{room}").to(get_car_laps))
+            .service(web::resource("/rooms").to(list_rooms))
+            .service(web::resource("/rooms/{room}/recording")
+                .route(web::post().to(start_recording))
+                .route(web::delete().to(stop_recording)))
+            .service(web::resource("/replay")
+                .route(web::get().to(get_replay))
+                .route(web::post().to(control_replay)))
+            .service(web::resource("/metrics").to(get_metrics))
+            .service(web::resource("/healthz").to(get_health))
+            .service(web::resource("/readyz").to(get_readiness))
+    });
+
+    if let Some(workers) = settings.workers {
+        server = server.workers(workers);
+    }
+
+    for addr in &settings.bind {
+        server = match &tls {
+            Some(config) => {
+                info!("Listening on {} (TLS)", addr);
+                server.bind_rustls(addr, config.clone())?
+            },
+
+            None => {
+                info!("Listening on {}", addr);
+                server.bind(addr)?
+            }
+        };
+    }
+
+    server.run().await
 }
 
-async fn get_session(_req: HttpRequest, _state: web::Data<AppState>) -> impl Responder {
-    web::Json(())
+async fn get_session(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let session = room.send(server::GetSession).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let session = match session {
+        Some(s) => s,
+        None => return Ok(HttpResponse::ServiceUnavailable().body("No session data has been received yet"))
+    };
+
+    let body = json(&*session)?;
+    let etag = entity_tag(&body);
+
+    if etag_matches(&req, &etag) {
+        return Ok(HttpResponse::NotModified().header(header::ETAG, etag).finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, "no-cache")
+        .content_type("application/json")
+        .body(body))
 }
 
-async fn connect_client(req: HttpRequest, stream: web::Payload, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    ws::start(client::WsTelemetryClient::new(state.get_ref().server_addr.clone()), &req, stream)
+async fn get_timing(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let timing = room.send(server::GetTiming).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    match timing {
+        Some(t) => Ok(HttpResponse::Ok().json(&*t)),
+        None => Ok(HttpResponse::ServiceUnavailable().body("No telemetry has been received yet"))
+    }
+}
+
+async fn get_standings(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let standings = room.send(server::GetStandings).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    match standings {
+        Some(s) => Ok(HttpResponse::Ok().json(&*s)),
+        None => Ok(HttpResponse::ServiceUnavailable().body("No session data and telemetry have been received yet"))
+    }
+}
+
+async fn get_event_log(req: HttpRequest, query: web::Query<events::EventQuery>, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let events = room.send(server::GetEvents(query.into_inner())).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(events))
+}
+
+/// Get the laps completed by every car in the current session.
+async fn get_laps(req: HttpRequest, opts: web::Query<LapOptions>, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let table = room.send(server::GetLaps).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let table = match table {
+        Some(t) => t,
+        None => return Ok(HttpResponse::ServiceUnavailable().body("No telemetry has been received yet"))
+    };
+
+    match lap_format(&req, &opts) {
+        laps::LapFormat::Json => Ok(HttpResponse::Ok().json(table)),
+        laps::LapFormat::Csv => Ok(HttpResponse::Ok().content_type("text/csv").body(laps::to_csv(&table.cars)))
+    }
+}
+
+/// Get the laps completed by one car in the current session.
+async fn get_car_laps(req: HttpRequest, opts: web::Query<LapOptions>, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let car_idx: usize = req.match_info().query("car_idx").parse()
+        .map_err(|_| error::ErrorBadRequest("Invalid car index"))?;
+
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let car = room.send(server::GetCarLaps(car_idx)).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let car = match car {
+        Some(Some(c)) => c,
+        Some(None) => return Ok(HttpResponse::NotFound().body("No such car")),
+        None => return Ok(HttpResponse::ServiceUnavailable().body("No telemetry has been received yet"))
+    };
+
+    match lap_format(&req, &opts) {
+        laps::LapFormat::Json => Ok(HttpResponse::Ok().json(car)),
+        laps::LapFormat::Csv => Ok(HttpResponse::Ok().content_type("text/csv").body(laps::to_csv(Some(&car))))
+    }
+}
+
+/// Get the track and weather conditions of the current session as numbers with units.
+async fn get_conditions(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let session = room.send(server::GetSession).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    match session {
+        Some(s) => Ok(HttpResponse::Ok().json(s.weekend.conditions())),
+        None => Ok(HttpResponse::ServiceUnavailable().body("No session data has been received yet"))
+    }
+}
+
+async fn list_rooms(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let rooms = state.rooms.send(rooms::ListRooms).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let mut infos = Vec::with_capacity(rooms.len());
+
+    for room in rooms {
+        infos.push(room.send(server::GetRoomInfo).await.map_err(error::ErrorInternalServerError)?);
+    }
+
+    Ok(HttpResponse::Ok().json(infos))
+}
+
+async fn start_recording(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if let Some(denied) = state.authorize_admin(&req) {
+        return Ok(denied);
+    }
+
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let started = room.send(server::StartRecording { dir: state.record_dir.clone() }).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    if started {
+        Ok(HttpResponse::Created().finish())
+    } else {
+        Ok(HttpResponse::Conflict().body("Room is already being recorded"))
+    }
+}
+
+async fn stop_recording(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if let Some(denied) = state.authorize_admin(&req) {
+        return Ok(denied);
+    }
+
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let stopped = room.send(server::StopRecording).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    if stopped {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().body("Room is not being recorded"))
+    }
+}
+
+/// Replay a recording into a room in place of a live source.
+async fn start_replay(state: &AppState, settings: &Settings, path: &str) -> io::Result<Addr<replay::Replay>> {
+    if !rooms::is_valid_name(&settings.replay_room) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid replay room name"));
+    }
+
+    let entries = recorder::read(Path::new(path))?;
+
+    if entries.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Recording {} is empty", path)));
+    }
+
+    let room = state.rooms.send(rooms::JoinRoom { name: settings.replay_room.clone() }).await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    info!("Replaying {} into room '{}'", path, settings.replay_room);
+
+    Ok(replay::Replay::new(room, state.metrics.clone(), entries, settings.replay_speed, settings.replay_loop).start())
+}
+
+async fn get_replay(state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let replay = match &state.replay {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No replay is running"))
+    };
+
+    let status = replay.send(replay::GetStatus).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(status))
+}
+
+async fn control_replay(req: HttpRequest, control: web::Json<replay::Control>, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if let Some(denied) = state.authorize_admin(&req) {
+        return Ok(denied);
+    }
+
+    let replay = match &state.replay {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No replay is running"))
+    };
+
+    replay.send(control.into_inner()).await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn get_metrics(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.metrics.render())
+}
+
+async fn get_health() -> HttpResponse {
+    HttpResponse::Ok().body("OK")
+}
+
+/// Report whether a source is connected and sending fresh telemetry and session details.
+async fn get_readiness(state: web::Data<AppState>) -> HttpResponse {
+    let max_age = state.ready_max_age.as_secs_f64();
+    let fresh = |age: Option<f64>| age.map_or(false, |a| a <= max_age);
+
+    let sources = state.metrics.sources.load(Ordering::Relaxed);
+    let telemetry_age = state.metrics.telemetry_age();
+    let session_age = state.metrics.session_age();
+
+    let readiness = Readiness {
+        ready: sources > 0 && fresh(telemetry_age) && fresh(session_age),
+        sources: sources,
+        telemetry_age: telemetry_age,
+        session_age: session_age
+    };
+
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+async fn connect_client(req: HttpRequest, stream: web::Payload, opts: web::Query<ClientOptions>, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if let Some(denied) = state.admit_viewer(&req) {
+        return Ok(denied);
+    }
+
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let protocol = req.headers().get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|h| h.to_str().ok())
+        .and_then(client::Encoding::from_protocols);
+
+    let encoding = opts.encoding.or(protocol).unwrap_or_default();
+
+    let client = client::WsTelemetryClient::new(room, state.metrics.clone(), state.heartbeat, encoding, opts.max_hz);
+
+    ws::start_with_protocols(client, &client::PROTOCOLS, &req, stream)
+}
+
+/// Stream a room to a viewer as Server-Sent Events, resuming after the `Last-Event-ID` if it is given.
+async fn stream_events(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if let Some(denied) = state.admit_viewer(&req) {
+        return Ok(denied);
+    }
+
+    let room = match state.get_room(&req).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body("No such room"))
+    };
+
+    let last_seq = req.headers().get("Last-Event-ID")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|id| id.trim().parse::<u64>().ok());
+
+    let (client, events) = sse::SseClient::new(room, state.metrics.clone(), state.heartbeat, last_seq);
+    client.start();
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .streaming(events))
 }
 
 async fn connect_source(req: HttpRequest, stream: web::Payload, state: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    debug!("Telemetry Streaming Request: {:?}", req);
-    ws::start(source::Source::new(state.get_ref().server_addr.clone()), &req, stream)
+    debug!("Source connecting from {:?} to {}", req.peer_addr(), req.path());
+
+    if !state.authorize_source(&req) {
+        warn!("Rejected source connection from {:?}: invalid stream password", req.peer_addr());
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let room = state.join_room(&req).await?;
+
+    let source = source::Source::new(room, state.metrics.clone(), state.heartbeat, state.source_stale_timeout);
+
+    ws::start(source, &req, stream)
 }
 
 impl AppState {
-    pub fn new() -> Self {
-        let srv  = server::TelemetryServer::default();
-        let addr = srv.start();
+    pub fn new(settings: &Settings) -> Self {
+        let metrics = Arc::new(metrics::Metrics::default());
+        let record_dir = PathBuf::from(&settings.record_dir);
+        let registry = rooms::RoomRegistry::new(metrics.clone(), if settings.record { Some(record_dir.clone()) } else { None });
+        let addr = registry.start();
 
         Self {
-            server_addr: addr,
-            stream_password: String::default()
+            rooms: addr,
+            heartbeat: settings.heartbeat(),
+            max_viewers: settings.max_viewers,
+            ready_max_age: Duration::from_millis(settings.ready_max_age),
+            source_stale_timeout: Duration::from_millis(settings.source_stale_timeout),
+            metrics: metrics,
+            stream_password: settings.stream_password.clone(),
+            admin_password: settings.admin_password.clone(),
+            record_dir: record_dir,
+            replay: None
         }
     }
+
+    /// Get the server for the room named in the request path, creating it if required.
+    ///
+    /// Requests without a room are directed to the default room. Only sources may create rooms.
+    async fn join_room(&self, req: &HttpRequest) -> Result<Addr<server::TelemetryServer>, Error> {
+        let name = room_name(req)?;
+
+        self.rooms.send(rooms::JoinRoom { name: name.to_owned() }).await
+            .map_err(error::ErrorInternalServerError)
+    }
+
+    /// Get the server for the room named in the request path if it exists.
+    async fn get_room(&self, req: &HttpRequest) -> Result<Option<Addr<server::TelemetryServer>>, Error> {
+        let name = room_name(req)?;
+
+        self.rooms.send(rooms::GetRoom { name: name.to_owned() }).await
+            .map_err(error::ErrorInternalServerError)
+    }
+
+    /// Check there is room for another viewer.
+    ///
+    /// Returns the response to send when the viewer limit has been reached.
+    fn admit_viewer(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        if self.max_viewers > 0 && self.metrics.viewers.load(Ordering::Relaxed) >= self.max_viewers {
+            warn!("Rejected viewer from {:?}: limit of {} viewers reached", req.peer_addr(), self.max_viewers);
+            return Some(HttpResponse::ServiceUnavailable().body("Too many viewers"));
+        }
+
+        None
+    }
+
+    /// Check the stream password presented by a source.
+    ///
+    /// The password is sent as an `Authorization: Bearer` header.
+    /// When no stream password is configured every source is accepted.
+    fn authorize_source(&self, req: &HttpRequest) -> bool {
+        if self.stream_password.is_empty() {
+            return true;
+        }
+
+        match bearer_token(req) {
+            Some(password) => secrets_match(password, &self.stream_password),
+            None => false
+        }
+    }
+
+    /// Check the admin password sent as an `Authorization: Bearer` header.
+    ///
+    /// Returns the response to send when the request is not allowed.
+    /// Admin endpoints are disabled when no admin password is configured.
+    fn authorize_admin(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        if self.admin_password.is_empty() {
+            return Some(HttpResponse::Forbidden().body("Admin endpoints are disabled"));
+        }
+
+        match bearer_token(req) {
+            Some(password) if secrets_match(password, &self.admin_password) => None,
+            _ => Some(HttpResponse::Unauthorized().finish())
+        }
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers().get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+/// Compare a presented secret with the expected one in time independent of where they differ.
+fn secrets_match(presented: &str, expected: &str) -> bool {
+    let (a, b) = (presented.as_bytes(), expected.as_bytes());
+
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Get the room named in the request path, or the default room.
+fn room_name(req: &HttpRequest) -> Result<&str, Error> {
+    let name = req.match_info().get("room").unwrap_or(rooms::DEFAULT_ROOM);
+
+    if rooms::is_valid_name(name) {
+        Ok(name)
+    } else {
+        Err(error::ErrorBadRequest("Invalid room name"))
+    }
+}
+
+/// Get the format lap history was requested in, from the `format` query parameter or the `Accept` header.
+fn lap_format(req: &HttpRequest, opts: &LapOptions) -> laps::LapFormat {
+    let accept = req.headers().get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+
+    opts.format.unwrap_or_else(|| laps::LapFormat::from_accept(accept))
+}
+
+/// Build a strong entity tag from a response body.
+fn entity_tag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Check whether the request's `If-None-Match` header matches the given entity tag.
+fn etag_matches(req: &HttpRequest, etag: &str) -> bool {
+    let if_none_match = match req.headers().get(header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) {
+        Some(h) => h,
+        None => return false
+    };
+
+    if_none_match.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
 }
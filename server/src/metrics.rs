@@ -0,0 +1,117 @@
+//! Counters describing the server's activity, exposed in the Prometheus text format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub viewers: AtomicUsize,
+    pub sources: AtomicUsize,
+    pub telemetry_received: AtomicU64,
+    pub sessions_received: AtomicU64,
+    pub telemetry_broadcast: AtomicU64,
+    pub sessions_broadcast: AtomicU64,
+    pub invalid_telemetry: AtomicU64,
+    pub invalid_sessions: AtomicU64,
+    pub dropped: AtomicU64,
+    pub evicted: AtomicU64,
+    last_source_frame: AtomicU64,
+    last_telemetry: AtomicU64,
+    last_session: AtomicU64
+}
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a frame was received from a source.
+    pub fn source_frame(&self) {
+        self.last_source_frame.store(now_millis(), Ordering::Relaxed);
+    }
+
+    /// Record receipt of valid telemetry from a source.
+    pub fn record_telemetry(&self) {
+        Self::inc(&self.telemetry_received);
+        self.last_telemetry.store(now_millis(), Ordering::Relaxed);
+    }
+
+    /// Record receipt of valid session details from a source.
+    pub fn record_session(&self) {
+        Self::inc(&self.sessions_received);
+        self.last_session.store(now_millis(), Ordering::Relaxed);
+    }
+
+    /// Time since a source last sent a frame, `None` if no frame has been received.
+    pub fn seconds_since_source_frame(&self) -> Option<f64> {
+        age(&self.last_source_frame)
+    }
+
+    /// Time since valid telemetry was last received, `None` if none has been received.
+    pub fn telemetry_age(&self) -> Option<f64> {
+        age(&self.last_telemetry)
+    }
+
+    /// Time since valid session details were last received, `None` if none have been received.
+    pub fn session_age(&self) -> Option<f64> {
+        age(&self.last_session)
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        gauge(&mut out, "viewers", "Number of connected viewers.", self.viewers.load(Ordering::Relaxed) as f64);
+        gauge(&mut out, "sources", "Number of connected sources.", self.sources.load(Ordering::Relaxed) as f64);
+
+        by_type(&mut out, "messages_received_total", "Messages received from sources.",
+            &self.telemetry_received, &self.sessions_received);
+        by_type(&mut out, "messages_broadcast_total", "Messages broadcast to the viewers of a room.",
+            &self.telemetry_broadcast, &self.sessions_broadcast);
+        by_type(&mut out, "parse_failures_total", "Messages from sources which could not be parsed.",
+            &self.invalid_telemetry, &self.invalid_sessions);
+
+        counter(&mut out, "dropped_messages_total", "Telemetry dropped for viewers which fell behind.", self.dropped.load(Ordering::Relaxed));
+        counter(&mut out, "evicted_viewers_total", "Viewers disconnected for falling behind.", self.evicted.load(Ordering::Relaxed));
+
+        gauge(&mut out, "seconds_since_last_source_frame", "Time since a frame was last received from a source.",
+            self.seconds_since_source_frame().unwrap_or(std::f64::NAN));
+
+        out
+    }
+}
+
+/// Milliseconds since the UNIX epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Seconds since a timestamp recorded with `now_millis`, `None` if it was never recorded.
+fn age(stamp: &AtomicU64) -> Option<f64> {
+    match stamp.load(Ordering::Relaxed) {
+        0 => None,
+        last => Some(now_millis().saturating_sub(last) as f64 / 1000f64)
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP iracing_ws_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE iracing_ws_{} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "iracing_ws_{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "iracing_ws_{} {}", name, value);
+}
+
+fn by_type(out: &mut String, name: &str, help: &str, telemetry: &AtomicU64, session: &AtomicU64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "iracing_ws_{}{{type=\"telemetry\"}} {}", name, telemetry.load(Ordering::Relaxed));
+    let _ = writeln!(out, "iracing_ws_{}{{type=\"session\"}} {}", name, session.load(Ordering::Relaxed));
+}
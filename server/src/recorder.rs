@@ -0,0 +1,302 @@
+//! `Recorder` is an actor which archives the telemetry and session data received by a room.
+//!
+//! Recordings are newline delimited JSON `Entry`s, written as a series of gzip members
+//! which are each complete once flushed. A crash only loses the entries since the last flush,
+//! and a recording can be read with any multi-member gzip reader.
+//! A new file is started whenever the sub-session changes.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::prelude::*;
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Serialize, Deserialize};
+
+use crate::metrics::now_millis;
+use protocol::{TelemetryData, SessionDetails};
+
+/// Interval at which buffered entries are compressed and written to disk.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Frame {
+    #[serde(rename = "T")]
+    Telemetry(Arc<TelemetryData>),
+
+    #[serde(rename = "S")]
+    Session(Arc<SessionDetails>)
+}
+
+/// A frame and the time it was received (ms since the UNIX epoch).
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct Entry {
+    pub received: u64,
+    pub frame: Frame
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    room: String,
+    file: Option<File>,
+    sub_session_id: Option<i32>,
+    buffer: GzEncoder<Vec<u8>>
+}
+
+/// Finish the recording and stop the recorder.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Stop;
+
+impl Entry {
+    pub fn new(frame: Frame) -> Self {
+        Self { received: now_millis(), frame: frame }
+    }
+}
+
+impl Recorder {
+    pub fn new(dir: &Path, room: &str) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            room: room.to_owned(),
+            file: None,
+            sub_session_id: None,
+            buffer: new_buffer()
+        }
+    }
+
+    /// Start a new recording file for a sub-session.
+    fn rotate(&mut self, sub_session_id: i32) -> io::Result<()> {
+        self.flush()?;
+
+        fs::create_dir_all(&self.dir)?;
+
+        let path = self.dir.join(format!("{}-{}-{}.jsonl.gz", self.room, sub_session_id, now_millis()));
+        info!("Recording '{}' to {}", self.room, path.display());
+
+        self.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        self.sub_session_id = Some(sub_session_id);
+
+        Ok(())
+    }
+
+    /// Write buffered entries to the file as a complete gzip member.
+    fn flush(&mut self) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(f) => f,
+            None => return Ok(())
+        };
+
+        let buffer = std::mem::replace(&mut self.buffer, new_buffer());
+
+        if buffer.get_ref().is_empty() {
+            return Ok(());
+        }
+
+        file.write_all(&buffer.finish()?)?;
+        file.sync_data()
+    }
+
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        if let Frame::Session(session) = &entry.frame {
+            let id = session.weekend.sub_session_id;
+
+            if self.sub_session_id != Some(id) {
+                self.rotate(id)?;
+            }
+        }
+
+        // Frames are only recorded once the sub-session they belong to is known.
+        if self.file.is_none() {
+            return Ok(());
+        }
+
+        serde_json::to_writer(&mut self.buffer, entry)?;
+        self.buffer.write_all(b"\n")
+    }
+}
+
+impl Actor for Recorder {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(FLUSH_INTERVAL, |act, _ctx| {
+            if let Err(e) = act.flush() {
+                error!("Unable to write recording of '{}': {}", act.room, e);
+            }
+        });
+    }
+
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+        if let Err(e) = self.flush() {
+            error!("Unable to write recording of '{}': {}", self.room, e);
+        }
+
+        info!("Stopped recording '{}'", self.room);
+
+        Running::Stop
+    }
+}
+
+impl Handler<Entry> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: Entry, _ctx: &mut Context<Self>) {
+        if let Err(e) = self.record(&msg) {
+            error!("Unable to record '{}': {}", self.room, e);
+        }
+    }
+}
+
+impl Handler<Stop> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, _: Stop, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
+
+///
+/// Read all entries from a recording.
+///
+/// A truncated final gzip member, as left by a crash, ends the recording rather than failing it.
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    let mut entries = Vec::new();
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!("Recording {} is truncated", path.display());
+                break;
+            },
+            Err(e) => return Err(e)
+        };
+
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping invalid entry in {}: {}", path.display(), e)
+        }
+    }
+
+    Ok(entries)
+}
+
+fn new_buffer() -> GzEncoder<Vec<u8>> {
+    GzEncoder::new(Vec::new(), Compression::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    /// An empty directory for a test's recordings.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("recorder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn recordings(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+        paths.sort();
+
+        paths
+    }
+
+    fn session(sub_session_id: i32) -> Entry {
+        let mut session = fixtures::session();
+        session.weekend.sub_session_id = sub_session_id;
+
+        Entry::new(Frame::Session(Arc::new(session)))
+    }
+
+    fn telemetry(session_number: i32) -> Entry {
+        Entry::new(Frame::Telemetry(Arc::new(TelemetryData { session_number: session_number, ..Default::default() })))
+    }
+
+    fn session_numbers(entries: &[Entry]) -> Vec<Option<i32>> {
+        entries.iter().map(|e| match &e.frame {
+            Frame::Telemetry(telem) => Some(telem.session_number),
+            Frame::Session(_) => None
+        }).collect()
+    }
+
+    #[test]
+    fn flushes_are_read_back_as_one_recording() {
+        let dir = test_dir("flushes");
+        let mut recorder = Recorder::new(&dir, "room");
+
+        recorder.record(&session(1)).unwrap();
+        recorder.record(&telemetry(1)).unwrap();
+        recorder.flush().unwrap();
+        recorder.record(&telemetry(2)).unwrap();
+        recorder.flush().unwrap();
+
+        let paths = recordings(&dir);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(session_numbers(&read(&paths[0]).unwrap()), vec![None, Some(1), Some(2)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_last_member_keeps_the_complete_entries() {
+        let dir = test_dir("truncated");
+        let mut recorder = Recorder::new(&dir, "room");
+
+        recorder.record(&session(1)).unwrap();
+        recorder.record(&telemetry(1)).unwrap();
+        recorder.flush().unwrap();
+
+        let path = recordings(&dir).remove(0);
+        let complete = fs::metadata(&path).unwrap().len();
+
+        recorder.record(&telemetry(2)).unwrap();
+        recorder.record(&telemetry(3)).unwrap();
+        recorder.flush().unwrap();
+
+        // As if the server crashed part way through writing the second member.
+        let written = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(complete + (written - complete) / 2).unwrap();
+
+        assert_eq!(session_numbers(&read(&path).unwrap()), vec![None, Some(1)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_sub_session_starts_a_new_file() {
+        let dir = test_dir("rotation");
+        let mut recorder = Recorder::new(&dir, "room");
+
+        // Nothing is recorded until the sub-session is known.
+        recorder.record(&telemetry(0)).unwrap();
+
+        recorder.record(&session(1)).unwrap();
+        recorder.record(&telemetry(1)).unwrap();
+        recorder.record(&session(1)).unwrap();
+        recorder.record(&session(2)).unwrap();
+        recorder.record(&telemetry(2)).unwrap();
+        recorder.flush().unwrap();
+
+        let paths = recordings(&dir);
+        assert_eq!(paths.len(), 2);
+
+        let first = paths.iter().find(|p| p.to_string_lossy().contains("room-1-")).unwrap();
+        let second = paths.iter().find(|p| p.to_string_lossy().contains("room-2-")).unwrap();
+
+        assert_eq!(session_numbers(&read(first).unwrap()), vec![None, Some(1), None]);
+        assert_eq!(session_numbers(&read(second).unwrap()), vec![None, Some(2)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+//! `Replay` is an actor which plays a recording into a room as if it were a live source.
+//!
+//! Frames are sent with their original spacing, scaled by the playback speed. Playback can
+//! be paused, resumed, sought and looped while it runs.
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use actix::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::metrics::Metrics;
+use crate::recorder::{Entry, Frame};
+use crate::server::{self, TelemetryServer};
+
+pub struct Replay {
+    server: Addr<TelemetryServer>,
+    metrics: Arc<Metrics>,
+    entries: Vec<Entry>,
+    position: usize,
+    speed: f64,
+    looping: bool,
+    paused: bool,
+    next: Option<SpawnHandle>
+}
+
+/// Commands controlling playback.
+#[derive(Message, Debug, Deserialize)]
+#[rtype(result = "()")]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Control {
+    Pause,
+    Resume,
+
+    /// Jump to a time (s) from the start of the recording.
+    Seek { seconds: f64 },
+
+    /// Set the playback speed multiplier.
+    Speed { multiplier: f64 },
+
+    /// Restart from the beginning when the recording ends.
+    Loop { enabled: bool }
+}
+
+/// Request the current playback status.
+#[derive(Message, Debug)]
+#[rtype(result = "Status")]
+pub struct GetStatus;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub position: f64,
+    pub duration: f64,
+    pub speed: f64,
+    pub paused: bool,
+    pub looping: bool,
+    pub finished: bool
+}
+
+/// The slowest playback speed, which keeps the delay between frames within a `Duration`.
+pub const MIN_SPEED: f64 = 0.001;
+
+/// Check a playback speed multiplier is usable, i.e. finite and at least `MIN_SPEED`.
+pub fn is_valid_speed(speed: f64) -> bool {
+    speed.is_finite() && speed >= MIN_SPEED
+}
+
+impl Replay {
+    pub fn new(server: Addr<TelemetryServer>, metrics: Arc<Metrics>, entries: Vec<Entry>, speed: f64, looping: bool) -> Self {
+        assert!(is_valid_speed(speed), "Invalid replay speed {}", speed);
+
+        Self {
+            server: server,
+            metrics: metrics,
+            entries: entries,
+            position: 0,
+            speed: speed,
+            looping: looping,
+            paused: false,
+            next: None
+        }
+    }
+
+    /// Time (ms) of an entry relative to the start of the recording.
+    fn offset(&self, idx: usize) -> u64 {
+        match (self.entries.first(), self.entries.get(idx)) {
+            (Some(first), Some(entry)) => entry.received.saturating_sub(first.received),
+            (Some(first), None) => self.entries.last().map(|l| l.received.saturating_sub(first.received)).unwrap_or(0),
+            _ => 0
+        }
+    }
+
+    /// Schedule the next frame, replacing any frame which was already scheduled.
+    fn schedule(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.next.take() {
+            ctx.cancel_future(handle);
+        }
+
+        if self.paused {
+            return;
+        }
+
+        if self.position >= self.entries.len() {
+            if !self.looping {
+                info!("Replay finished");
+                return;
+            }
+
+            self.position = 0;
+        }
+
+        let delay = if self.position == 0 {
+            0
+        } else {
+            self.offset(self.position).saturating_sub(self.offset(self.position - 1))
+        };
+
+        let delay = Duration::from_secs_f64(delay as f64 / 1000f64 / self.speed);
+
+        self.next = Some(ctx.run_later(delay, |act, ctx| {
+            act.next = None;
+            act.play();
+            act.schedule(ctx);
+        }));
+    }
+
+    /// Send the frame at the current position and advance.
+    fn play(&mut self) {
+        if let Some(entry) = self.entries.get(self.position) {
+            self.send(entry.frame.clone());
+            self.position += 1;
+        }
+    }
+
+    fn send(&self, frame: Frame) {
+        self.metrics.source_frame();
+
+        match frame {
+            Frame::Telemetry(telem) => {
+                self.metrics.record_telemetry();
+                self.server.do_send((*telem).clone());
+            },
+
+            Frame::Session(session) => {
+                self.metrics.record_session();
+                self.server.do_send((*session).clone());
+            }
+        }
+    }
+
+    /// Move playback to a time from the start, sending the session in effect at that point.
+    fn seek(&mut self, seconds: f64) {
+        let target = (seconds.max(0f64) * 1000f64) as u64;
+
+        self.position = (0..self.entries.len())
+            .find(|&idx| self.offset(idx) >= target)
+            .unwrap_or_else(|| self.entries.len());
+
+        let session = self.entries[..self.position].iter().rev()
+            .find(|e| matches!(e.frame, Frame::Session(_)))
+            .map(|e| e.frame.clone());
+
+        if let Some(session) = session {
+            self.send(session);
+        }
+    }
+}
+
+impl Actor for Replay {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("Replaying {} frames ({:.0}s) at {}x speed", self.entries.len(), self.offset(self.entries.len()) as f64 / 1000f64, self.speed);
+
+        self.metrics.sources.fetch_add(1, Ordering::Relaxed);
+        self.server.do_send(server::SourceJoined);
+        self.schedule(ctx);
+    }
+
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+        self.server.do_send(server::SourceLeft);
+        Running::Stop
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.metrics.sources.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Handler<Control> for Replay {
+    type Result = ();
+
+    fn handle(&mut self, msg: Control, ctx: &mut Context<Self>) {
+        debug!("Replay control: {:?}", msg);
+
+        match msg {
+            Control::Pause => self.paused = true,
+            Control::Resume => self.paused = false,
+            Control::Seek { seconds } => self.seek(seconds),
+            Control::Speed { multiplier } if is_valid_speed(multiplier) => self.speed = multiplier,
+            Control::Speed { multiplier } => warn!("Ignoring invalid replay speed {}", multiplier),
+            Control::Loop { enabled } => self.looping = enabled
+        }
+
+        self.schedule(ctx);
+    }
+}
+
+impl Handler<GetStatus> for Replay {
+    type Result = MessageResult<GetStatus>;
+
+    fn handle(&mut self, _: GetStatus, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(Status {
+            position: self.offset(self.position) as f64 / 1000f64,
+            duration: self.offset(self.entries.len()) as f64 / 1000f64,
+            speed: self.speed,
+            paused: self.paused,
+            looping: self.looping,
+            finished: self.position >= self.entries.len() && !self.looping
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speeds_must_be_finite_and_not_too_slow() {
+        assert!(is_valid_speed(1f64));
+        assert!(is_valid_speed(MIN_SPEED));
+
+        assert!(!is_valid_speed(0f64));
+        assert!(!is_valid_speed(-1f64));
+        assert!(!is_valid_speed(1e-300));
+        assert!(!is_valid_speed(f64::NAN));
+        assert!(!is_valid_speed(f64::INFINITY));
+    }
+}
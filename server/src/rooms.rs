@@ -0,0 +1,134 @@
+//! `RoomRegistry` is an actor which owns a `TelemetryServer` for each named room.
+//!
+//! Each room has its own source, cached session and set of viewers, allowing several
+//! races to be streamed through one server. Rooms other than the default are only
+//! created by sources, so viewers can't fill the server with empty rooms, and are closed
+//! once their sources and viewers have all gone.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use actix::prelude::*;
+
+use crate::server::{TelemetryServer, StartRecording};
+use crate::metrics::Metrics;
+
+/// Room used by the un-named `/source` and `/telemetry` endpoints.
+pub const DEFAULT_ROOM: &str = "default";
+
+const MAX_NAME_LENGTH: usize = 64;
+
+pub struct RoomRegistry {
+    rooms: BTreeMap<String, Addr<TelemetryServer>>,
+    metrics: Arc<Metrics>,
+    record_dir: Option<PathBuf>
+}
+
+/// Get the server for a room, creating the room if it doesn't exist yet.
+#[derive(Message, Debug)]
+#[rtype(result = "Addr<TelemetryServer>")]
+pub struct JoinRoom {
+    pub name: String
+}
+
+/// Get the server for an existing room.
+#[derive(Message, Debug)]
+#[rtype(result = "Option<Addr<TelemetryServer>>")]
+pub struct GetRoom {
+    pub name: String
+}
+
+/// Remove a room whose sources and viewers have all gone. Sent by the room's server as it stops.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct CloseRoom {
+    pub name: String
+}
+
+/// List the servers of all rooms.
+#[derive(Message, Debug)]
+#[rtype(result = "Vec<Addr<TelemetryServer>>")]
+pub struct ListRooms;
+
+/// Check a room name is usable in a URL path.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl RoomRegistry {
+    /// Create the registry. When `record_dir` is given every room is recorded from its creation.
+    pub fn new(metrics: Arc<Metrics>, record_dir: Option<PathBuf>) -> Self {
+        Self { rooms: BTreeMap::new(), metrics: metrics, record_dir: record_dir }
+    }
+
+    /// Get the server for a room, creating it if it doesn't exist yet.
+    fn join(&mut self, name: &str, ctx: &mut Context<Self>) -> Addr<TelemetryServer> {
+        let metrics = &self.metrics;
+        let record_dir = &self.record_dir;
+
+        // The default room is never closed, so isn't told where to report it is empty.
+        let registry = if name == DEFAULT_ROOM {
+            None
+        } else {
+            Some(ctx.address().recipient())
+        };
+
+        let addr = self.rooms.entry(name.to_owned()).or_insert_with(|| {
+            info!("Creating room '{}'", name);
+            let addr = TelemetryServer::new(name, metrics.clone(), registry).start();
+
+            if let Some(dir) = record_dir {
+                addr.do_send(StartRecording { dir: dir.clone() });
+            }
+
+            addr
+        });
+
+        addr.clone()
+    }
+}
+
+impl Actor for RoomRegistry {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        // Viewers of the default room may connect before its source does.
+        self.join(DEFAULT_ROOM, ctx);
+    }
+}
+
+impl Handler<JoinRoom> for RoomRegistry {
+    type Result = MessageResult<JoinRoom>;
+
+    fn handle(&mut self, msg: JoinRoom, ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.join(&msg.name, ctx))
+    }
+}
+
+impl Handler<GetRoom> for RoomRegistry {
+    type Result = MessageResult<GetRoom>;
+
+    fn handle(&mut self, msg: GetRoom, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.rooms.get(&msg.name).cloned())
+    }
+}
+
+impl Handler<CloseRoom> for RoomRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseRoom, _ctx: &mut Context<Self>) {
+        if msg.name != DEFAULT_ROOM && self.rooms.remove(&msg.name).is_some() {
+            info!("Closed room '{}'", msg.name);
+        }
+    }
+}
+
+impl Handler<ListRooms> for RoomRegistry {
+    type Result = MessageResult<ListRooms>;
+
+    fn handle(&mut self, _: ListRooms, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.rooms.values().cloned().collect())
+    }
+}
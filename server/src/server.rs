@@ -1,50 +1,151 @@
 //! `TelemetryServer` is an actor that maintains the client connections and manages data streams.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use actix::prelude::*;
-use crate::session::SessionDetails;
-use serde::{Deserialize,Serialize};
+use protocol::{TelemetryData, SessionDetails};
+use crate::metrics::{self, Metrics};
+use crate::recorder::{self, Recorder};
+use crate::timing::{Timing, TimingTable};
+use crate::standings::Standings;
+use crate::events::{Event, EventDetector, EventQuery};
+use crate::laps::{LapHistory, LapTable, CarLaps};
+use crate::rooms::CloseRoom;
+use serde::Serialize;
 
+/// Number of messages a viewer's mailbox holds before it is considered to be lagging.
+pub const VIEWER_QUEUE_SIZE: usize = 16;
+
+/// Number of consecutive broadcasts a viewer may lag behind for before it is disconnected.
+const MAX_LAGGED_BROADCASTS: u32 = 40;
+
+/// Number of broadcasts kept for viewers resuming a stream they were disconnected from.
+pub const HISTORY_SIZE: usize = 64;
 
 // Messages are encoded as (and passed as) strings.
-#[derive(Message,Clone)]
-#[rtype(result = "()")]
+#[derive(Clone,Debug)]
 pub enum Message {
-    Telemetry(TelemetryData),
-    Session(SessionDetails)
+    Telemetry(Arc<TelemetryData>),
+    Session(Arc<SessionDetails>),
+
+    /// Lap timing, sent whenever a car completes a lap or the session changes.
+    Timing(Arc<TimingTable>),
+
+    /// Standings, sent whenever a car's position or pit status changes.
+    Standings(Arc<Standings>),
+
+    /// A race event, such as an overtake or a pit stop.
+    Event(Arc<Event>),
+
+    /// The state of the room's source has changed.
+    Source(SourceState),
+
+    /// The server is closing the viewer's connection.
+    Close(String)
+}
+
+/// A message sent to a viewer, with its place in the room's stream.
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "()")]
+pub struct Update {
+    pub stamp: Stamp,
+    pub message: Message
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stamp {
+    /// Number of the broadcast which carried the message. Increases by one with each broadcast in the room.
+    pub seq: u64,
+
+    /// Time the message was sent (ms since the UNIX epoch).
+    pub timestamp: u64,
+
+    /// Whether the message re-sends the room's current state to one viewer, rather than being broadcast.
+    ///
+    /// Such messages carry the `seq` of the last broadcast, so they don't have a place in the stream of their own.
+    pub snapshot: bool
 }
 
+/// State of the source feeding a room.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceState {
+    /// A source is connected and sending data.
+    Connected,
+
+    /// No source is connected.
+    Disconnected,
+
+    /// A source is connected but has stopped sending data.
+    Stale
+}
+
+/// Notification from a source that its state has changed.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct SourceStatus(pub SourceState);
+
+/// A source has started feeding the room.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct SourceJoined;
+
+/// A source feeding the room has stopped. The room is only disconnected once all of its sources have.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct SourceLeft;
+
 
 #[derive(Debug,Clone)]
 pub struct TelemetryServer {
-    connections: BTreeMap<usize, Recipient<Message>>,
+    pub name: String,
+    connections: BTreeMap<usize, Viewer>,
     pub cnt: usize,
-    pub session_data: Option<SessionDetails>
+    seq: u64,
+    metrics: Arc<Metrics>,
+    pub session_data: Option<Arc<SessionDetails>>,
+    pub telemetry_data: Option<Arc<TelemetryData>>,
+    pub source_state: SourceState,
+    sources: usize,
+    timing: Timing,
+    timing_table: Option<Arc<TimingTable>>,
+    standings: Option<Arc<Standings>>,
+    events: EventDetector,
+    laps: LapHistory,
+    history: VecDeque<Update>,
+    recorder: Option<Addr<Recorder>>,
+
+    /// Where to report the room is empty, `None` if the room is kept open.
+    registry: Option<Recipient<CloseRoom>>
 }
 
-#[derive(Message,Debug,Default,Serialize,Deserialize,Clone)]
-#[rtype(result = "()")]
-pub struct TelemetryData {
-    pub air_temperature: f32,
-    pub state: i32,
-    pub flags: u32,
-    pub session_number: i32,
-    pub time_remaining: f32,
-    pub track_temperature: f32,
-    pub car_class_positions: Vec<i32>,
-    pub car_positions: Vec<i32>,
-    pub car_gears: Vec<i32>,
-    pub car_rpms: Vec<f32>,
-    pub car_steers: Vec<f32>,
-    pub car_laps: Vec<i32>,
-    pub car_laps_perc: Vec<f32>,
-    pub car_pits: Vec<bool>
+///
+/// A connected viewer and the messages waiting for room in its mailbox.
+///
+/// Only the newest telemetry is kept in the backlog, session messages are all kept.
+#[derive(Debug,Clone)]
+struct Viewer {
+    addr: Recipient<Update>,
+    backlog: VecDeque<Update>,
+    lagged: u32
+}
+
+/// Outcome of delivering a message to a viewer.
+enum Delivery {
+    Sent,
+    Lagging { dropped: usize },
+    Closed
 }
 
 #[derive(Message, Debug)]
 #[rtype(usize)]
 pub struct Connect {
-    pub addr: Recipient<Message>
+    pub addr: Recipient<Update>,
+
+    /// Sequence number of the last message the viewer received before reconnecting, if resuming.
+    pub last_seq: Option<u64>
 }
 
 #[derive(Message, Debug)]
@@ -53,24 +154,284 @@ pub struct Disconnect {
     pub id: usize
 }
 
+/// Re-send the room's current state to a viewer, e.g. after it changed how it is sent frames.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Refresh {
+    pub id: usize
+}
+
+/// Request the most recently received session details, if any.
+#[derive(Message, Debug)]
+#[rtype(result = "Option<Arc<SessionDetails>>")]
+pub struct GetSession;
 
+/// Request the current lap timing, if any telemetry has been received.
+#[derive(Message, Debug)]
+#[rtype(result = "Option<Arc<TimingTable>>")]
+pub struct GetTiming;
 
-impl Default for TelemetryServer {
-    fn default() -> Self {
+/// Request the current standings, if session details and telemetry have been received.
+#[derive(Message, Debug)]
+#[rtype(result = "Option<Arc<Standings>>")]
+pub struct GetStandings;
+
+/// Request the logged events matching a query.
+#[derive(Message, Debug)]
+#[rtype(result = "Vec<Event>")]
+pub struct GetEvents(pub EventQuery);
+
+/// Request the laps completed by every car in the current session, if any telemetry has been received.
+#[derive(Message, Debug)]
+#[rtype(result = "Option<LapTable>")]
+pub struct GetLaps;
+
+///
+/// Request the laps completed by a car in the current session.
+///
+/// `None` before any telemetry has been received, `Some(None)` if the car isn't in the session.
+#[derive(Message, Debug)]
+#[rtype(result = "Option<Option<CarLaps>>")]
+pub struct GetCarLaps(pub usize);
+
+/// Start recording the room to the given directory. Returns false if it was already recording.
+#[derive(Message, Debug)]
+#[rtype(result = "bool")]
+pub struct StartRecording {
+    pub dir: PathBuf
+}
+
+/// Stop recording the room. Returns false if it wasn't recording.
+#[derive(Message, Debug)]
+#[rtype(result = "bool")]
+pub struct StopRecording;
+
+/// Request a summary of the room this server is streaming.
+#[derive(Message, Debug)]
+#[rtype(result = "RoomInfo")]
+pub struct GetRoomInfo;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoomInfo {
+    pub name: String,
+    pub viewers: usize,
+    pub source: SourceState,
+    pub recording: bool,
+    pub track_name: Option<String>,
+    pub sub_session_id: Option<i32>
+}
+
+
+
+impl TelemetryServer {
+    pub fn new(name: &str, metrics: Arc<Metrics>, registry: Option<Recipient<CloseRoom>>) -> Self {
         Self {
+            name: name.to_owned(),
             session_data: None,
+            telemetry_data: None,
+            source_state: SourceState::Disconnected,
+            sources: 0,
+            timing: Timing::default(),
+            timing_table: None,
+            standings: None,
+            events: EventDetector::default(),
+            laps: LapHistory::default(),
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            recorder: None,
+            registry: registry,
             cnt: 0,
+            seq: 0,
+            metrics: metrics,
             connections: BTreeMap::new()
         }
     }
+
+    /// Tell viewers the state of the room's source, if it has changed.
+    fn set_source_state(&mut self, state: SourceState) {
+        if self.source_state == state {
+            return;
+        }
+
+        info!("Source for '{}' is now {:?}", self.name, state);
+
+        self.source_state = state;
+        self.broadcast(Message::Source(state));
+    }
+
+    /// Close the room once its sources and viewers have all gone, unless it is kept open.
+    fn close_if_empty(&mut self, ctx: &mut Context<Self>) {
+        if self.sources > 0 || !self.connections.is_empty() {
+            return;
+        }
+
+        if let Some(registry) = &self.registry {
+            info!("Closing empty room '{}'", self.name);
+
+            let _ = registry.do_send(CloseRoom { name: self.name.clone() });
+
+            if let Some(rec) = self.recorder.take() {
+                rec.do_send(recorder::Stop);
+            }
+
+            ctx.stop();
+        }
+    }
+
+    /// Rebuild the standings, broadcasting them if they have changed.
+    fn update_standings(&mut self) {
+        let standings = match (&self.session_data, &self.telemetry_data) {
+            (Some(session), Some(telem)) => Standings::new(session, telem),
+            _ => return
+        };
+
+        if self.standings.as_ref().map_or(false, |s| **s == standings) {
+            return;
+        }
+
+        let standings = Arc::new(standings);
+
+        self.standings = Some(standings.clone());
+        self.broadcast(Message::Standings(standings));
+    }
+
+    fn broadcast_events(&mut self, events: Vec<Event>) {
+        for event in events {
+            self.broadcast(Message::Event(Arc::new(event)));
+        }
+    }
+
+    /// Stamp a message with the room's current place in its stream.
+    fn stamp(&self, msg: Message) -> Update {
+        Update {
+            stamp: Stamp { seq: self.seq, timestamp: metrics::now_millis(), snapshot: false },
+            message: msg
+        }
+    }
+
+    /// The messages which bring a viewer up to date with the room's current state.
+    fn snapshot(&self) -> Vec<Update> {
+        let mut messages = vec![Message::Source(self.source_state)];
+
+        if let Some(session) = &self.session_data {
+            messages.push(Message::Session(session.clone()));
+        }
+
+        if let Some(telem) = &self.telemetry_data {
+            messages.push(Message::Telemetry(telem.clone()));
+        }
+
+        if let Some(timing) = &self.timing_table {
+            messages.push(Message::Timing(timing.clone()));
+        }
+
+        if let Some(standings) = &self.standings {
+            messages.push(Message::Standings(standings.clone()));
+        }
+
+        messages.into_iter()
+            .map(|m| {
+                let mut update = self.stamp(m);
+                update.stamp.snapshot = true;
+                update
+            })
+            .collect()
+    }
+
+    ///
+    /// The broadcasts a viewer missed since `last_seq`.
+    ///
+    /// Returns `None` when they are no longer all in the history, and the viewer needs a snapshot instead.
+    fn missed(&self, last_seq: u64) -> Option<Vec<Update>> {
+        if last_seq > self.seq {
+            return None;
+        }
+
+        let oldest = self.history.front().map_or(self.seq + 1, |u| u.stamp.seq);
+
+        if oldest > last_seq + 1 {
+            return None;
+        }
+
+        Some(self.history.iter().filter(|u| u.stamp.seq > last_seq).cloned().collect())
+    }
+
+    fn broadcast(&mut self, msg: Message) {
+        let mut closed = Vec::new();
+        let mut evicted = Vec::new();
+
+        self.seq += 1;
+        let update = self.stamp(msg);
+
+        if self.history.len() >= HISTORY_SIZE {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(update.clone());
+
+        for (id, viewer) in self.connections.iter_mut() {
+            match viewer.deliver(update.clone()) {
+                Delivery::Sent => (),
+                Delivery::Closed => closed.push(*id),
+                Delivery::Lagging { dropped } => {
+                    self.metrics.dropped.fetch_add(dropped as u64, Ordering::Relaxed);
+
+                    if viewer.lagged > MAX_LAGGED_BROADCASTS {
+                        evicted.push(*id);
+                    }
+                }
+            }
+        }
+
+        for id in closed {
+            self.connections.remove(&id);
+        }
+
+        for id in evicted {
+            if let Some(viewer) = self.connections.remove(&id) {
+                warn!("Disconnecting viewer {} from '{}': lagging {} broadcasts behind", id, self.name, viewer.lagged);
+
+                Metrics::inc(&self.metrics.evicted);
+                let _ = viewer.addr.do_send(self.stamp(Message::Close("Connection too slow".to_owned())));
+            }
+        }
+    }
 }
 
+impl Viewer {
+    fn new(addr: Recipient<Update>) -> Self {
+        Self { addr: addr, backlog: VecDeque::new(), lagged: 0 }
+    }
 
-impl TelemetryServer {
-    fn broadcast(&self, msg: &Message) {
-        for con in self.connections.values() {
-            let _ = con.do_send(msg.to_owned());
+    /// Queue a message and send as much of the backlog as the viewer's mailbox accepts.
+    fn deliver(&mut self, update: Update) -> Delivery {
+        let mut dropped = 0;
+
+        if let Message::Telemetry(_) = update.message {
+            let before = self.backlog.len();
+            self.backlog.retain(|u| !matches!(u.message, Message::Telemetry(_)));
+            dropped = before - self.backlog.len();
         }
+
+        self.backlog.push_back(update);
+
+        while let Some(m) = self.backlog.pop_front() {
+            match self.addr.try_send(m) {
+                Ok(()) => (),
+
+                Err(SendError::Full(m)) => {
+                    self.backlog.push_front(m);
+                    self.lagged += 1;
+
+                    return Delivery::Lagging { dropped: dropped };
+                },
+
+                Err(SendError::Closed(_)) => return Delivery::Closed
+            }
+        }
+
+        self.lagged = 0;
+
+        Delivery::Sent
     }
 }
 
@@ -82,12 +443,23 @@ impl Handler<Connect>  for TelemetryServer {
     type Result = usize;
 
     fn handle(&mut self, msg: Connect, _ctx: &mut Context<Self>) -> Self::Result {
-        info!("User Connected: {:?}", msg);
+        info!("User Connected to '{}': {:?}", self.name, msg);
 
         self.cnt += 1;
         let id = self.cnt;
 
-        self.connections.insert(id, msg.addr);
+        // Bring the new client up to date straight away rather than waiting for the next update.
+        match msg.last_seq.and_then(|seq| self.missed(seq)) {
+            Some(missed) => for update in missed {
+                let _ = msg.addr.do_send(update);
+            },
+
+            None => for update in self.snapshot() {
+                let _ = msg.addr.do_send(update);
+            }
+        }
+
+        self.connections.insert(id, Viewer::new(msg.addr));
 
         info!("There are now {} connected users", self.connections.len());
 
@@ -98,22 +470,65 @@ impl Handler<Connect>  for TelemetryServer {
 impl Handler<Disconnect> for TelemetryServer {
     type Result = ();
 
-    fn handle(&mut self, msg: Disconnect, _ctx: &mut Context<Self>) -> Self::Result {
-        info!("User disconnected");
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) -> Self::Result {
+        info!("User disconnected from '{}'", self.name);
 
         self.connections.remove(&msg.id);
 
         info!("There are now {} connected users", self.connections.len());
+
+        self.close_if_empty(ctx);
     }
 
 }
 
+impl Handler<Refresh> for TelemetryServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Refresh, _ctx: &mut Context<Self>) {
+        let addr = match self.connections.get(&msg.id) {
+            Some(viewer) => viewer.addr.clone(),
+            None => return
+        };
+
+        for update in self.snapshot() {
+            let _ = addr.do_send(update);
+        }
+    }
+}
+
 impl Handler<TelemetryData> for TelemetryServer {
     type Result = ();
 
     // Handle receipt of a new telemetry by broadcasting to all clients
-    fn handle(&mut self, msg: TelemetryData, _ctx: &mut Context<Self>) {
-        self.broadcast(&Message::Telemetry(msg));
+    fn handle(&mut self, mut msg: TelemetryData, _ctx: &mut Context<Self>) {
+        // Telemetry from recordings is deserialized without the decoded fields.
+        msg.fill_decoded();
+
+        let telem = Arc::new(msg);
+
+        if let Some(rec) = &self.recorder {
+            rec.do_send(recorder::Entry::new(recorder::Frame::Telemetry(telem.clone())));
+        }
+
+        let received = metrics::now_millis();
+        let timing_changed = self.timing.update(&telem, received);
+        let events = self.events.telemetry(&telem, received);
+        self.laps.telemetry(&telem);
+
+        self.telemetry_data = Some(telem.clone());
+        self.broadcast(Message::Telemetry(telem));
+        Metrics::inc(&self.metrics.telemetry_broadcast);
+
+        if timing_changed {
+            let timing = Arc::new(self.timing.table());
+
+            self.timing_table = Some(timing.clone());
+            self.broadcast(Message::Timing(timing));
+        }
+
+        self.update_standings();
+        self.broadcast_events(events);
     }
 }
 
@@ -122,7 +537,153 @@ impl Handler<SessionDetails> for TelemetryServer {
     
     // Handle receipt of a new session by broadcasting to all clients
     fn handle(&mut self, msg: SessionDetails, _ctx: &mut Context<Self>) {
-        self.session_data = Some(msg.clone());
-        self.broadcast(&Message::Session(msg));
+        let session = Arc::new(msg);
+
+        if let Some(rec) = &self.recorder {
+            rec.do_send(recorder::Entry::new(recorder::Frame::Session(session.clone())));
+        }
+
+        let events = self.events.session(&session, metrics::now_millis());
+        self.laps.session(&session);
+
+        self.session_data = Some(session.clone());
+        self.broadcast(Message::Session(session));
+        Metrics::inc(&self.metrics.sessions_broadcast);
+
+        self.update_standings();
+        self.broadcast_events(events);
+    }
+}
+
+impl Handler<GetSession> for TelemetryServer {
+    type Result = Option<Arc<SessionDetails>>;
+
+    fn handle(&mut self, _: GetSession, _ctx: &mut Context<Self>) -> Self::Result {
+        self.session_data.clone()
+    }
+}
+
+impl Handler<GetTiming> for TelemetryServer {
+    type Result = Option<Arc<TimingTable>>;
+
+    fn handle(&mut self, _: GetTiming, _ctx: &mut Context<Self>) -> Self::Result {
+        self.timing_table.clone()
+    }
+}
+
+impl Handler<GetStandings> for TelemetryServer {
+    type Result = Option<Arc<Standings>>;
+
+    fn handle(&mut self, _: GetStandings, _ctx: &mut Context<Self>) -> Self::Result {
+        self.standings.clone()
+    }
+}
+
+impl Handler<GetEvents> for TelemetryServer {
+    type Result = MessageResult<GetEvents>;
+
+    fn handle(&mut self, msg: GetEvents, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.events.query(&msg.0))
+    }
+}
+
+impl Handler<GetLaps> for TelemetryServer {
+    type Result = MessageResult<GetLaps>;
+
+    fn handle(&mut self, _: GetLaps, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.laps.table())
+    }
+}
+
+impl Handler<GetCarLaps> for TelemetryServer {
+    type Result = MessageResult<GetCarLaps>;
+
+    fn handle(&mut self, msg: GetCarLaps, _ctx: &mut Context<Self>) -> Self::Result {
+        if !self.laps.started() {
+            return MessageResult(None);
+        }
+
+        MessageResult(Some(self.laps.car(msg.0)))
+    }
+}
+
+impl Handler<GetRoomInfo> for TelemetryServer {
+    type Result = MessageResult<GetRoomInfo>;
+
+    fn handle(&mut self, _: GetRoomInfo, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(RoomInfo {
+            name: self.name.clone(),
+            viewers: self.connections.len(),
+            source: self.source_state,
+            recording: self.recorder.is_some(),
+            track_name: self.session_data.as_ref().map(|s| s.weekend.track_display_name.clone()),
+            sub_session_id: self.session_data.as_ref().map(|s| s.weekend.sub_session_id)
+        })
+    }
+}
+
+impl Handler<SourceStatus> for TelemetryServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SourceStatus, _ctx: &mut Context<Self>) {
+        self.set_source_state(msg.0);
+    }
+}
+
+impl Handler<SourceJoined> for TelemetryServer {
+    type Result = ();
+
+    fn handle(&mut self, _: SourceJoined, _ctx: &mut Context<Self>) {
+        self.sources += 1;
+        self.set_source_state(SourceState::Connected);
+    }
+}
+
+impl Handler<SourceLeft> for TelemetryServer {
+    type Result = ();
+
+    fn handle(&mut self, _: SourceLeft, ctx: &mut Context<Self>) {
+        self.sources = self.sources.saturating_sub(1);
+
+        if self.sources == 0 {
+            self.set_source_state(SourceState::Disconnected);
+            self.close_if_empty(ctx);
+        }
+    }
+}
+
+impl Handler<StartRecording> for TelemetryServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: StartRecording, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.recorder.is_some() {
+            return false;
+        }
+
+        let rec = Recorder::new(&msg.dir, &self.name).start();
+
+        // Start the recording from the current session, rather than waiting for the next update.
+        if let Some(session) = &self.session_data {
+            rec.do_send(recorder::Entry::new(recorder::Frame::Session(session.clone())));
+        }
+
+        self.recorder = Some(rec);
+
+        true
+    }
+}
+
+impl Handler<StopRecording> for TelemetryServer {
+    type Result = bool;
+
+    fn handle(&mut self, _: StopRecording, _ctx: &mut Context<Self>) -> Self::Result {
+        match self.recorder.take() {
+            Some(rec) => {
+                rec.do_send(recorder::Stop);
+                true
+            },
+
+            None => false
+        }
     }
 }
\ No newline at end of file
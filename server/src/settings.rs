@@ -0,0 +1,191 @@
+//! Server settings, layered from defaults, a configuration file, environment variables and
+//! command line flags, each overriding the last.
+
+use std::env;
+use std::time::Duration;
+use serde::{Serialize,Deserialize};
+use config::{Config, ConfigError, Environment, File};
+
+use crate::replay;
+
+#[derive(Deserialize,Serialize,Clone,Debug)]
+pub struct Settings {
+    pub stream_password: String,
+    pub bind: Vec<String>,
+    pub workers: Option<usize>,
+    pub heartbeat_interval: u64,
+    pub client_timeout: u64,
+    pub max_viewers: usize,
+    pub ready_max_age: u64,
+    pub source_stale_timeout: u64,
+    pub admin_password: String,
+    pub record: bool,
+    pub record_dir: String,
+    pub replay: Option<String>,
+    pub replay_room: String,
+    pub replay_speed: f64,
+    pub replay_loop: bool,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_reload_interval: u64
+}
+
+/// Interval at which a connection is pinged, and how long it may go without a response.
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration
+}
+
+impl Settings {
+    ///
+    /// Load the settings.
+    ///
+    /// The configuration file is `server.(toml|json|yaml)` in the working directory unless another is given with `--config`.
+    /// Environment variables are prefixed with `SERVER_`, e.g. `SERVER_MAX_VIEWERS`.
+    /// Command line flags are the setting name with dashes, e.g. `--max-viewers 100`. `--bind` may be given more than once.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut cfg = Config::default();
+        cfg.set_default("stream_password", "")?;
+        cfg.set_default("bind", vec!["0.0.0.0:8088"])?;
+        cfg.set_default("heartbeat_interval", 1000)?;
+        cfg.set_default("client_timeout", 10000)?;
+        cfg.set_default("max_viewers", 0)?;
+        cfg.set_default("ready_max_age", 10000)?;
+        cfg.set_default("source_stale_timeout", 5000)?;
+        cfg.set_default("admin_password", "")?;
+        cfg.set_default("record", false)?;
+        cfg.set_default("record_dir", "recordings")?;
+        cfg.set_default("replay_room", "default")?;
+        cfg.set_default("replay_speed", 1.0)?;
+        cfg.set_default("replay_loop", false)?;
+        cfg.set_default("tls_reload_interval", 10000)?;
+
+        let flags = parse_flags(env::args().skip(1))?;
+        let file = flags.iter()
+            .find(|(name, _)| name == "config")
+            .map(|(_, path)| path.clone())
+            .unwrap_or_else(|| "server".to_owned());
+
+        cfg.merge(File::with_name(&file).required(file != "server"))?;
+        cfg.merge(Environment::with_prefix("SERVER"))?;
+
+        // Environment variables can only hold a single string, so allow a comma separated list of addresses.
+        if let Ok(binds) = cfg.get_str("bind") {
+            cfg.set("bind", split_list(&binds))?;
+        }
+
+        let mut binds = Vec::new();
+
+        for (name, value) in flags {
+            match name.as_str() {
+                "config" => (),
+                "bind" => binds.push(value),
+                _ => { cfg.set(&name, value)?; }
+            }
+        }
+
+        if !binds.is_empty() {
+            cfg.set("bind", binds)?;
+        }
+
+        let settings: Self = cfg.try_into()?;
+
+        if !replay::is_valid_speed(settings.replay_speed) {
+            return Err(ConfigError::Message(format!("Invalid replay_speed {}, must be at least {}", settings.replay_speed, replay::MIN_SPEED)));
+        }
+
+        Ok(settings)
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        Heartbeat {
+            interval: Duration::from_millis(self.heartbeat_interval),
+            timeout: Duration::from_millis(self.client_timeout)
+        }
+    }
+}
+
+/// Parse `--name value` and `--name=value` flags into setting names and values.
+fn parse_flags<I: Iterator<Item = String>>(mut args: I) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut flags = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(f) => f,
+            None => return Err(ConfigError::Message(format!("Unexpected argument '{}'", arg)))
+        };
+
+        let (name, value) = match flag.find('=') {
+            Some(idx) => (flag[..idx].to_owned(), flag[idx + 1..].to_owned()),
+            None => match args.next() {
+                Some(value) => (flag.to_owned(), value),
+                None => return Err(ConfigError::Message(format!("Missing value for '--{}'", flag)))
+            }
+        };
+
+        flags.push((name.replace('-', "_"), value));
+    }
+
+    Ok(flags)
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_owned())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        list.iter().map(|a| a.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn flag(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn flags_take_separate_or_inline_values() {
+        let flags = parse_flags(args(&["--max-viewers", "100", "--replay-speed=2.5", "--bind", "a:1", "--bind=b:2"])).unwrap();
+
+        assert_eq!(flags, vec![
+            flag("max_viewers", "100"),
+            flag("replay_speed", "2.5"),
+            flag("bind", "a:1"),
+            flag("bind", "b:2")
+        ]);
+    }
+
+    #[test]
+    fn inline_values_may_contain_equals() {
+        let flags = parse_flags(args(&["--stream-password=a=b"])).unwrap();
+
+        assert_eq!(flags, vec![flag("stream_password", "a=b")]);
+    }
+
+    #[test]
+    fn arguments_must_be_flags() {
+        match parse_flags(args(&["--workers", "2", "extra"])) {
+            Err(ConfigError::Message(msg)) => assert_eq!(msg, "Unexpected argument 'extra'"),
+            other => panic!("Expected an error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn flags_need_a_value() {
+        match parse_flags(args(&["--record-dir"])) {
+            Err(ConfigError::Message(msg)) => assert_eq!(msg, "Missing value for '--record-dir'"),
+            other => panic!("Expected an error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn lists_are_split_and_trimmed() {
+        assert_eq!(split_list("0.0.0.0:80, [::]:80,,"), vec!["0.0.0.0:80".to_owned(), "[::]:80".to_owned()]);
+    }
+}
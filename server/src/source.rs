@@ -1,20 +1,55 @@
 //! Source is a singleton actor which receives the session & telemetry data
 //! from the iRacing exporter and passes it to the TelemetryServer
 
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
 use actix::prelude::*;
 use actix_web_actors::ws;
 
 use crate::server;
-use crate::session;
-use serde_json::from_str;
+use crate::metrics::{self, Metrics};
+use crate::settings::Heartbeat;
+use protocol::envelope::{self, Hello, Message};
 
 #[derive(Clone,Debug)]
 pub struct Source {
-    server: Addr<server::TelemetryServer>
+    hb: Instant,
+    heartbeat: Heartbeat,
+    last_frame: Instant,
+    stale_timeout: Duration,
+    stale: bool,
+    enveloped: bool,
+    server: Addr<server::TelemetryServer>,
+    metrics: Arc<Metrics>
 }
 
 impl Actor for Source {
     type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.metrics.sources.fetch_add(1, Ordering::Relaxed);
+        self.hb(ctx);
+
+        // The room may have closed since it was joined, the exporter reconnects and opens it again.
+        self.server.send(server::SourceJoined).into_actor(self).then(|res, _act, ctx| {
+            if res.is_err() {
+                ctx.stop();
+            }
+
+            fut::ready(())
+        }).wait(ctx);
+    }
+
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+        self.server.do_send(server::SourceLeft);
+        Running::Stop
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.metrics.sources.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Source {
@@ -27,7 +62,13 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Source {
             }
         };
 
+        self.hb = Instant::now();
+
         match payload {
+            ws::Message::Ping(ping) => {
+                ctx.pong(&ping);
+            }
+
             ws::Message::Close(_) => {
                 info!("Closing souce stream");
                 ctx.stop();
@@ -35,40 +76,59 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Source {
 
             ws::Message::Text(txt) => {
                 let raw = txt.as_str();
+                self.metrics.source_frame();
+                self.last_frame = Instant::now();
 
-                match txt.chars().next().unwrap() {
-                    'T' => {
-                        trace!("Got Telemetry");
-                        let telem_pkt = &raw[1..];
+                if self.stale {
+                    info!("Source resumed sending data");
+                    self.stale = false;
+                    self.server.do_send(server::SourceStatus(server::SourceState::Connected));
+                }
 
-                        match from_str::<server::TelemetryData>(telem_pkt) {
-                            Ok(t) =>  {
-                               self.server.do_send(t) 
-                            }
-                            Err(e) => {
-                                error!("Invalid telemetry: {}", e);
-                            } 
-                        };
+                // Sources which haven't completed the handshake send legacy frames.
+                let decoded = if envelope::is_envelope(raw) {
+                    envelope::decode(raw).map(|e| e.data)
+                } else {
+                    protocol::decode(raw).map(Message::from)
+                };
 
-                    } 
+                match decoded {
+                    Ok(Message::Telemetry(_)) | Ok(Message::Session(_)) if envelope::is_envelope(raw) && !self.enveloped => {
+                        warn!("Source sent an envelope without a hello for protocol version {}", envelope::PROTOCOL_VERSION);
+                    }
 
-                    'S' => {
+                    Ok(Message::Telemetry(t)) => {
+                        trace!("Got Telemetry");
+                        self.metrics.record_telemetry();
+                        self.server.do_send(t)
+                    }
+
+                    Ok(Message::Session(s)) => {
                         trace!("Got Session");
-                        let session_pkt = &raw[1..];
+                        self.metrics.record_session();
+                        self.server.do_send(s)
+                    }
 
-                        match from_str::<session::SessionDetails>(session_pkt) {
-                            Ok(s) => {
-                                self.server.do_send(s)
-                            },
+                    Ok(Message::Hello(hello)) => {
+                        self.welcome(hello, ctx);
+                    }
+
+                    Ok(Message::Welcome(_)) => {
+                        warn!("Source sent an unexpected welcome");
+                    }
 
-                            Err(e) => {
-                                error!("Invalid Session: {}", e);
-                            }
-                        };
+                    Err(e @ protocol::Error::Invalid(protocol::TELEMETRY, _)) => {
+                        Metrics::inc(&self.metrics.invalid_telemetry);
+                        error!("Invalid telemetry: {}", e);
                     }
 
-                    a @ _ => {
-                        warn!("Unknown data type: '{:?}'", a);
+                    Err(e @ protocol::Error::Invalid(protocol::SESSION, _)) => {
+                        Metrics::inc(&self.metrics.invalid_sessions);
+                        error!("Invalid Session: {}", e);
+                    }
+
+                    Err(e) => {
+                        warn!("Unknown data: {}", e);
                     }
                 }
             }
@@ -79,9 +139,56 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Source {
 }
 
 impl Source {
-    pub fn new(server_addr: Addr<server::TelemetryServer>) -> Self {
+    pub fn new(server_addr: Addr<server::TelemetryServer>, metrics: Arc<Metrics>, heartbeat: Heartbeat, stale_timeout: Duration) -> Self {
         Self {
-            server: server_addr
+            hb: Instant::now(),
+            heartbeat: heartbeat,
+            last_frame: Instant::now(),
+            stale_timeout: stale_timeout,
+            stale: false,
+            enveloped: false,
+            server: server_addr,
+            metrics: metrics
         }
     }
+
+    ///
+    /// Answer a source's hello with the server's protocol version.
+    ///
+    /// Sources which speak it send enveloped frames from then on, others must keep sending legacy frames.
+    fn welcome(&mut self, hello: Hello, ctx: &mut ws::WebsocketContext<Self>) {
+        info!("Source is {} {} (protocol version {})", hello.name, hello.version, hello.protocol_version);
+
+        self.enveloped = hello.protocol_version == envelope::PROTOCOL_VERSION;
+
+        if !self.enveloped {
+            warn!("Source speaks protocol version {}, not {}; only legacy frames are accepted", hello.protocol_version, envelope::PROTOCOL_VERSION);
+        }
+
+        let welcome = Hello::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), &["json"]);
+
+        match envelope::encode(&Message::Welcome(welcome), 0, metrics::now_millis()) {
+            Ok(text) => ctx.text(text),
+            Err(e) => error!("Unable to encode welcome: {}", e)
+        }
+    }
+
+    /// Ping the source, disconnecting it if it stops responding and marking it stale if it stops sending data.
+    fn hb(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.heartbeat.timeout {
+                warn!("Source timed out, disconnecting");
+                ctx.stop();
+                return;
+            }
+
+            if !act.stale && Instant::now().duration_since(act.last_frame) > act.stale_timeout {
+                warn!("Source has not sent data for {:?}", act.stale_timeout);
+                act.stale = true;
+                act.server.do_send(server::SourceStatus(server::SourceState::Stale));
+            }
+
+            ctx.ping(b"PING");
+        });
+    }
 }
\ No newline at end of file
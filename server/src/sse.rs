@@ -0,0 +1,156 @@
+//! SseClient is an actor which streams a room to a viewer as Server-Sent Events.
+//!
+//! Each message is sent as an event named after its type, e.g. `telemetry` or `session`, with the
+//! room's sequence number as its id so a reconnecting viewer can resume with `Last-Event-ID`.
+//! The snapshot of the room's state sent when a stream opens has no ids, so a viewer which drops
+//! part way through it resumes from the last broadcast it received, or is sent the snapshot again.
+use crate::server::{self, Stamp};
+use crate::settings::Heartbeat;
+use crate::metrics::Metrics;
+use protocol::envelope;
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use actix::prelude::*;
+use actix_web::Error;
+use actix_web::web::Bytes;
+use futures::channel::mpsc;
+use serde::Serialize;
+use serde_json::to_string as json;
+
+/// The body of an event stream response.
+pub type EventStream = mpsc::Receiver<Result<Bytes, Error>>;
+
+pub struct SseClient {
+    id: usize,
+    heartbeat: Heartbeat,
+    metrics: Arc<Metrics>,
+    last_seq: Option<u64>,
+    stream: mpsc::Sender<Result<Bytes, Error>>,
+    server: Addr<server::TelemetryServer>
+}
+
+impl Actor for SseClient {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(server::VIEWER_QUEUE_SIZE);
+        self.metrics.viewers.fetch_add(1, Ordering::Relaxed);
+        self.hb(ctx);
+
+        let addr = ctx.address();
+
+        self.server.send(server::Connect {
+            addr: addr.recipient(),
+            last_seq: self.last_seq
+        }).into_actor(self).then(|res, act, ctx| {
+            match res {
+                Ok(id) => act.id = id,
+                _ => ctx.stop(),
+            }
+
+            fut::ready(())
+        }).wait(ctx);
+    }
+
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+        self.server.do_send(server::Disconnect {
+            id: self.id
+        });
+        Running::Stop
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.metrics.viewers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Handler<server::Update> for SseClient {
+    type Result = ();
+
+    fn handle(&mut self, update: server::Update, ctx: &mut Self::Context) -> Self::Result {
+        let stamp = update.stamp;
+
+        match update.message {
+            server::Message::Telemetry(telem) => self.send('T', &*telem, stamp, ctx),
+            server::Message::Session(session) => self.send('S', &*session, stamp, ctx),
+            server::Message::Timing(timing) => self.send('L', &*timing, stamp, ctx),
+            server::Message::Standings(standings) => self.send('P', &*standings, stamp, ctx),
+            server::Message::Event(event) => self.send('E', &*event, stamp, ctx),
+            server::Message::Source(state) => self.send('F', state, stamp, ctx),
+
+            server::Message::Close(reason) => {
+                info!("Closing event stream {}: {}", self.id, reason);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+impl SseClient {
+    /// Create a client and the stream of events to respond to the viewer with.
+    pub fn new(server_addr: Addr<server::TelemetryServer>, metrics: Arc<Metrics>, heartbeat: Heartbeat, last_seq: Option<u64>) -> (Self, EventStream) {
+        // Room for the whole history, which a resuming viewer is sent before the response starts streaming.
+        let (tx, rx) = mpsc::channel(server::HISTORY_SIZE + server::VIEWER_QUEUE_SIZE);
+
+        let client = Self {
+            id: 0,
+            heartbeat: heartbeat,
+            metrics: metrics,
+            last_seq: last_seq,
+            stream: tx,
+            server: server_addr
+        };
+
+        (client, rx)
+    }
+
+    /// Send a message as an event named after its type.
+    fn send<T: Serialize>(&mut self, kind: char, data: T, stamp: Stamp, ctx: &mut Context<Self>) {
+        let event = match (envelope::type_name(kind), json(&data)) {
+            (Some(name), Ok(data)) if stamp.snapshot => format!("event: {}\ndata: {}\n\n", name, data),
+            (Some(name), Ok(data)) => format!("id: {}\nevent: {}\ndata: {}\n\n", stamp.seq, name, data),
+
+            (None, _) => {
+                error!("No event name for '{}' frames", kind);
+                return;
+            },
+
+            (_, Err(e)) => {
+                error!("Unable to encode event as JSON: {}", e);
+                return;
+            }
+        };
+
+        match self.stream.try_send(Ok(Bytes::from(event))) {
+            Ok(()) => (),
+
+            // Newer telemetry will follow, anything else can't be skipped without the viewer missing it.
+            Err(e) if e.is_full() && kind == 'T' => {
+                Metrics::inc(&self.metrics.dropped);
+            },
+
+            Err(e) if e.is_full() => {
+                warn!("Closing event stream {}: viewer is not keeping up", self.id);
+
+                // The viewer can reconnect with the last id it received and resume from the history.
+                Metrics::inc(&self.metrics.evicted);
+                ctx.stop();
+            },
+
+            Err(_) => ctx.stop()
+        }
+    }
+
+    /// Send a comment periodically to keep the connection open, and notice when the viewer has gone.
+    fn hb(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
+            if let Err(e) = act.stream.try_send(Ok(Bytes::from_static(b": ping\n\n"))) {
+                if e.is_disconnected() {
+                    ctx.stop();
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,142 @@
+//! Standings join the drivers in the session details with their live positions from telemetry.
+
+use serde::Serialize;
+
+use protocol::{TelemetryData, SessionDetails};
+
+/// One entry per car taking part in the session, ordered by position.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Standings {
+    pub cars: Vec<Standing>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Standing {
+    pub car_idx: usize,
+    pub position: i32,
+    pub class_position: i32,
+    pub car_number: u64,
+    pub driver_name: String,
+    pub team_name: String,
+    pub car_class: String,
+    pub car_class_color: String,
+    pub i_rating: i64,
+    pub license: String,
+    pub on_pit_road: bool
+}
+
+impl Standings {
+    ///
+    /// Build the standings from the latest session details and telemetry.
+    ///
+    /// Spectators and the pace car are left out, as are cars without a position which aren't on track.
+    /// Cars without a position are listed after those with one.
+    pub fn new(session: &SessionDetails, telem: &TelemetryData) -> Self {
+        let mut cars: Vec<Standing> = session.drivers.other_drivers.iter()
+            .filter(|d| d.is_spectator == 0 && !d.is_pace_car())
+            .filter_map(|d| {
+                let idx = d.index;
+                let position = telem.car_positions.get(idx).cloned().unwrap_or(0);
+                let on_track = telem.car_laps.get(idx).map_or(false, |&lap| lap >= 0);
+
+                if position <= 0 && !on_track {
+                    return None;
+                }
+
+                Some(Standing {
+                    car_idx: idx,
+                    position: position,
+                    class_position: telem.car_class_positions.get(idx).cloned().unwrap_or(0),
+                    car_number: d.car_number,
+                    driver_name: d.user_name.clone(),
+                    team_name: d.team_name.clone(),
+                    car_class: d.car_class_short_name.clone(),
+                    car_class_color: d.car_class_color.clone(),
+                    i_rating: d.i_rating,
+                    license: d.license.clone(),
+                    on_pit_road: telem.car_pits.get(idx).cloned().unwrap_or(false)
+                })
+            })
+            .collect();
+
+        cars.sort_by_key(|s| (s.position <= 0, s.position, s.car_idx));
+
+        Self { cars: cars }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{session, session_without_pace_car_flag};
+
+    fn telemetry(laps: &[i32], positions: &[i32], pits: &[bool]) -> TelemetryData {
+        TelemetryData {
+            car_laps: laps.to_vec(),
+            car_positions: positions.to_vec(),
+            car_class_positions: positions.to_vec(),
+            car_pits: pits.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// The fixture session with copies of "A Driver" in the given slots.
+    fn session_with_cars(slots: &[usize]) -> SessionDetails {
+        let mut session = session_without_pace_car_flag();
+        let driver = session.drivers.other_drivers[1].clone();
+
+        for &idx in slots {
+            let mut other = driver.clone();
+            other.index = idx;
+            other.user_name = format!("Driver {}", idx);
+            session.drivers.other_drivers.push(other);
+        }
+
+        session
+    }
+
+    fn order(standings: &Standings) -> Vec<usize> {
+        standings.cars.iter().map(|s| s.car_idx).collect()
+    }
+
+    #[test]
+    fn pace_car_is_left_out() {
+        let telem = telemetry(&[3, 3], &[2, 1], &[false, false]);
+
+        assert_eq!(order(&Standings::new(&session(), &telem)), vec![1]);
+        assert_eq!(order(&Standings::new(&session_without_pace_car_flag(), &telem)), vec![1]);
+    }
+
+    #[test]
+    fn spectators_are_left_out() {
+        let mut session = session_with_cars(&[2]);
+        session.drivers.other_drivers[2].is_spectator = 1;
+
+        let standings = Standings::new(&session, &telemetry(&[0, 3, 3], &[0, 1, 2], &[false, false, false]));
+
+        assert_eq!(order(&standings), vec![1]);
+    }
+
+    #[test]
+    fn cars_are_ordered_by_position() {
+        let session = session_with_cars(&[2, 3]);
+        let standings = Standings::new(&session, &telemetry(&[0, 3, 3, 3], &[0, 3, 1, 2], &[false, false, true, false]));
+
+        assert_eq!(order(&standings), vec![2, 3, 1]);
+        assert_eq!(standings.cars[0].position, 1);
+        assert_eq!(standings.cars[0].driver_name, "Driver 2");
+        assert!(standings.cars[0].on_pit_road);
+        assert_eq!(standings.cars[2].car_number, 23);
+    }
+
+    #[test]
+    fn cars_without_a_position_follow_the_rest() {
+        let session = session_with_cars(&[2, 3]);
+
+        // Car 3 is on track without a position yet, car 2 hasn't left the garage.
+        let standings = Standings::new(&session, &telemetry(&[0, 3, -1, 0], &[0, 1, 0, 0], &[false, false, false, false]));
+
+        assert_eq!(order(&standings), vec![1, 3]);
+        assert_eq!(standings.cars[1].position, 0);
+    }
+}
@@ -0,0 +1,122 @@
+//! Subscriptions allow a viewer to receive only some of the telemetry fields and cars.
+
+use std::collections::BTreeSet;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use protocol::TelemetryData;
+use protocol::telemetry;
+
+///
+/// A viewer's telemetry subscription.
+///
+/// `None` for either set means everything is subscribed to.
+/// When `cars` is set, each per-car vector only contains the subscribed cars, in the
+/// order given by an additional `car_idx` field.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Subscription {
+    pub fields: Option<BTreeSet<String>>,
+    pub cars: Option<BTreeSet<usize>>
+}
+
+impl Subscription {
+    /// Check if the subscription includes all fields and cars.
+    pub fn is_full(&self) -> bool {
+        self.fields.is_none() && self.cars.is_none()
+    }
+
+    /// Trim telemetry down to the subscribed fields and cars.
+    pub fn apply(&self, telem: &TelemetryData) -> Value {
+        let all = match serde_json::to_value(telem) {
+            Ok(Value::Object(map)) => map,
+            _ => return Value::Null
+        };
+
+        let mut trimmed = Map::new();
+
+        for (name, value) in all {
+            if let Some(fields) = &self.fields {
+                if !fields.contains(&name) {
+                    continue;
+                }
+            }
+
+            let value = match (&self.cars, value) {
+                (Some(cars), Value::Array(per_car)) if telemetry::is_per_car(&name) => {
+                    cars.iter().map(|&idx| per_car.get(idx).cloned().unwrap_or(Value::Null)).collect()
+                },
+
+                (_, value) => value
+            };
+
+            trimmed.insert(name, value);
+        }
+
+        if let Some(cars) = &self.cars {
+            trimmed.insert("car_idx".to_owned(), cars.iter().cloned().collect());
+        }
+
+        Value::Object(trimmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::SessionFlags;
+
+    fn telemetry() -> TelemetryData {
+        let mut telem = TelemetryData {
+            session_number: 2,
+            flags: (SessionFlags::WHITE | SessionFlags::GREEN).bits(),
+            car_positions: vec![3, 1, 2],
+            car_laps: vec![4, 5, 5],
+            ..Default::default()
+        };
+
+        telem.fill_decoded();
+        telem
+    }
+
+    fn set<T: Ord + Clone>(items: &[T]) -> Option<BTreeSet<T>> {
+        Some(items.iter().cloned().collect())
+    }
+
+    #[test]
+    fn default_subscription_is_full() {
+        assert!(Subscription::default().is_full());
+        assert!(!Subscription { fields: set(&["car_laps".to_owned()]), cars: None }.is_full());
+    }
+
+    #[test]
+    fn fields_are_trimmed() {
+        let sub = Subscription { fields: set(&["session_number".to_owned(), "car_laps".to_owned()]), cars: None };
+        let trimmed = sub.apply(&telemetry());
+
+        assert_eq!(trimmed, serde_json::json!({"session_number": 2, "car_laps": [4, 5, 5]}));
+    }
+
+    #[test]
+    fn cars_are_trimmed_and_indexed() {
+        let sub = Subscription { fields: set(&["car_positions".to_owned(), "session_number".to_owned()]), cars: set(&[0, 2]) };
+        let trimmed = sub.apply(&telemetry());
+
+        assert_eq!(trimmed, serde_json::json!({"session_number": 2, "car_positions": [3, 2], "car_idx": [0, 2]}));
+    }
+
+    #[test]
+    fn cars_outside_the_session_are_null() {
+        let sub = Subscription { fields: set(&["car_laps".to_owned()]), cars: set(&[1, 9]) };
+        let trimmed = sub.apply(&telemetry());
+
+        assert_eq!(trimmed, serde_json::json!({"car_laps": [5, null], "car_idx": [1, 9]}));
+    }
+
+    #[test]
+    fn session_fields_are_kept_whole() {
+        let sub = Subscription { fields: set(&["flags".to_owned(), "flags_decoded".to_owned()]), cars: set(&[0]) };
+        let trimmed = sub.apply(&telemetry());
+
+        assert_eq!(trimmed, serde_json::json!({"flags": 6, "flags_decoded": ["white", "green"], "car_idx": [0]}));
+    }
+}
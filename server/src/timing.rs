@@ -0,0 +1,299 @@
+//! Lap timing, worked out from the telemetry received by a room.
+//!
+//! The time each car crosses the start/finish line is interpolated between the telemetry
+//! received either side of the crossing, using `car_laps_perc`. Lap times and gaps are
+//! then the differences between these crossing times.
+
+use std::collections::BTreeMap;
+use serde::Serialize;
+
+use protocol::TelemetryData;
+
+/// Timing of every car in the session, in running order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TimingTable {
+    pub session_number: i32,
+    pub cars: Vec<CarTiming>
+}
+
+///
+/// Timing of a single car. Times are in seconds.
+///
+/// `gap` is the time behind the leader and `interval` the time behind the car ahead, both
+/// measured at the line on the car's last completed lap.
+#[derive(Debug, Clone, Serialize)]
+pub struct CarTiming {
+    pub car_idx: usize,
+    pub position: usize,
+    pub laps_completed: i32,
+    pub last_lap: Option<f64>,
+    pub best_lap: Option<f64>,
+    pub gap: Option<f64>,
+    pub interval: Option<f64>,
+    pub laps_down: i32
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Timing {
+    session_number: Option<i32>,
+    cars: BTreeMap<usize, Car>
+}
+
+#[derive(Debug, Clone, Default)]
+struct Car {
+    /// The previous telemetry sample: time (ms), lap and lap percentage.
+    sample: Option<(u64, i32, f32)>,
+
+    /// Time (ms) the car crossed the line to start each lap.
+    crossings: BTreeMap<i32, f64>,
+
+    last_lap: Option<f64>,
+    best_lap: Option<f64>,
+    position: i32
+}
+
+impl Timing {
+    ///
+    /// Update timing with telemetry received at `received` (ms since the UNIX epoch).
+    ///
+    /// Returns true when a car completed a lap, or the session changed.
+    pub fn update(&mut self, telem: &TelemetryData, received: u64) -> bool {
+        let mut changed = false;
+
+        if self.session_number != Some(telem.session_number) {
+            self.session_number = Some(telem.session_number);
+            self.cars.clear();
+            changed = true;
+        }
+
+        let cars = telem.car_laps.iter().zip(&telem.car_laps_perc).enumerate();
+
+        for (idx, (&lap, &perc)) in cars {
+            let car = self.cars.entry(idx).or_default();
+            car.position = telem.car_positions.get(idx).cloned().unwrap_or(0);
+
+            // Cars which aren't on track report a negative lap percentage.
+            if lap < 0 || perc < 0f32 {
+                car.sample = None;
+                continue;
+            }
+
+            if let Some((at, prev_lap, prev_perc)) = car.sample {
+                if lap > prev_lap {
+                    // Fraction of the distance covered since the previous sample at which the line was crossed.
+                    let travelled = (lap - prev_lap) as f64 + (perc - prev_perc) as f64;
+                    let fraction = if travelled > 0f64 { (1f64 - prev_perc as f64) / travelled } else { 1f64 };
+                    let crossed = at as f64 + fraction.min(1f64).max(0f64) * received.saturating_sub(at) as f64;
+
+                    car.complete(prev_lap + 1, crossed);
+                    changed = true;
+                }
+            }
+
+            car.sample = Some((received, lap, perc));
+        }
+
+        changed
+    }
+
+    /// Work out the timing table for the current state of the session.
+    pub fn table(&self) -> TimingTable {
+        let mut order: Vec<(&usize, &Car)> = self.cars.iter()
+            .filter(|(_, car)| car.sample.is_some() || !car.crossings.is_empty())
+            .collect();
+
+        // Cars without an official position yet are ordered by the distance they have covered.
+        order.sort_by(|(_, a), (_, b)| match (a.position > 0, b.position > 0) {
+            (true, true) => a.position.cmp(&b.position),
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            (false, false) => b.distance().partial_cmp(&a.distance()).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let leader = order.first().map(|(_, car)| *car);
+        let mut cars = Vec::with_capacity(order.len());
+
+        for (pos, (&idx, car)) in order.iter().enumerate() {
+            let ahead = if pos > 0 { Some(order[pos - 1].1) } else { None };
+
+            cars.push(CarTiming {
+                car_idx: idx,
+                position: pos + 1,
+                laps_completed: car.laps_completed(),
+                last_lap: car.last_lap,
+                best_lap: car.best_lap,
+                gap: leader.and_then(|l| car.behind(l)),
+                interval: ahead.and_then(|a| car.behind(a)),
+                laps_down: leader.map(|l| (l.distance() - car.distance()).floor().max(0f64) as i32).unwrap_or(0)
+            });
+        }
+
+        TimingTable {
+            session_number: self.session_number.unwrap_or(0),
+            cars: cars
+        }
+    }
+}
+
+impl Car {
+    /// Record the car crossing the line to start `lap` at `time` (ms).
+    fn complete(&mut self, lap: i32, time: f64) {
+        if let Some(start) = self.crossings.get(&(lap - 1)) {
+            let lap_time = (time - start) / 1000f64;
+
+            self.last_lap = Some(lap_time);
+            self.best_lap = Some(self.best_lap.map_or(lap_time, |best| best.min(lap_time)));
+        }
+
+        self.crossings.insert(lap, time);
+    }
+
+    fn laps_completed(&self) -> i32 {
+        self.crossings.keys().next_back().map_or(0, |lap| lap - 1)
+    }
+
+    /// Distance covered in laps, from the last sample.
+    fn distance(&self) -> f64 {
+        match self.sample {
+            Some((_, lap, perc)) => lap as f64 + perc as f64,
+            None => self.crossings.keys().next_back().cloned().unwrap_or(0) as f64
+        }
+    }
+
+    /// Time (s) this car crossed the line after `other`, on this car's last completed lap.
+    fn behind(&self, other: &Car) -> Option<f64> {
+        let (lap, time) = self.crossings.iter().next_back()?;
+        let other_time = other.crossings.get(lap)?;
+
+        Some(((time - other_time) / 1000f64).max(0f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Telemetry for cars on the given laps and lap percentages, in the given positions.
+    fn frame(session_number: i32, laps: &[(i32, f32)], positions: &[i32]) -> TelemetryData {
+        TelemetryData {
+            session_number: session_number,
+            car_laps: laps.iter().map(|&(lap, _)| lap).collect(),
+            car_laps_perc: laps.iter().map(|&(_, perc)| perc).collect(),
+            car_positions: positions.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Two cars, where car 0 crosses the line at 1.5s and car 1 at 2.5s.
+    fn race() -> Timing {
+        let mut timing = Timing::default();
+
+        timing.update(&frame(1, &[(1, 0.75), (1, 0.5)], &[1, 2]), 1000);
+        assert!(timing.update(&frame(1, &[(2, 0.25), (1, 0.75)], &[1, 2]), 2000));
+        assert!(timing.update(&frame(1, &[(2, 0.5), (2, 0.25)], &[1, 2]), 3000));
+
+        timing
+    }
+
+    #[test]
+    fn first_update_changes_the_session() {
+        let mut timing = Timing::default();
+
+        assert!(timing.update(&frame(1, &[(1, 0.5)], &[1]), 1000));
+        assert!(!timing.update(&frame(1, &[(1, 0.6)], &[1]), 2000));
+    }
+
+    #[test]
+    fn crossings_are_interpolated_into_lap_times() {
+        let mut timing = race();
+
+        // Car 0 crosses half way between samples at 103s and 104s, 102s after its first crossing.
+        timing.update(&frame(1, &[(2, 0.75), (2, 0.5)], &[1, 2]), 103000);
+        timing.update(&frame(1, &[(3, 0.25), (2, 0.6)], &[1, 2]), 104000);
+
+        let table = timing.table();
+        let car = &table.cars[0];
+
+        assert_eq!(car.car_idx, 0);
+        assert_eq!(car.laps_completed, 2);
+        assert_eq!(car.last_lap, Some(102.0));
+        assert_eq!(car.best_lap, Some(102.0));
+    }
+
+    #[test]
+    fn best_lap_is_the_fastest() {
+        let mut timing = Timing::default();
+
+        // Crossings at 1.5s, 101.5s, 191.5s and 286.5s, for laps of 100s, 90s and 95s.
+        for (lap, at) in [0, 100000, 190000, 285000].iter().enumerate() {
+            timing.update(&frame(1, &[(lap as i32 + 1, 0.75)], &[1]), at + 1000);
+            timing.update(&frame(1, &[(lap as i32 + 2, 0.25)], &[1]), at + 2000);
+        }
+
+        let table = timing.table();
+
+        assert_eq!(table.cars[0].last_lap, Some(95.0));
+        assert_eq!(table.cars[0].best_lap, Some(90.0));
+    }
+
+    #[test]
+    fn gaps_and_intervals_are_measured_at_the_line() {
+        let table = race().table();
+
+        assert_eq!(table.session_number, 1);
+        assert_eq!(table.cars.len(), 2);
+
+        assert_eq!(table.cars[0].gap, Some(0.0));
+        assert_eq!(table.cars[0].interval, None);
+
+        assert_eq!(table.cars[1].car_idx, 1);
+        assert_eq!(table.cars[1].position, 2);
+        assert_eq!(table.cars[1].gap, Some(1.0));
+        assert_eq!(table.cars[1].interval, Some(1.0));
+        assert_eq!(table.cars[1].laps_down, 0);
+    }
+
+    #[test]
+    fn lapped_cars_are_laps_down() {
+        let mut timing = Timing::default();
+
+        timing.update(&frame(1, &[(3, 0.5), (1, 0.25)], &[1, 2]), 1000);
+
+        let table = timing.table();
+
+        assert_eq!(table.cars[1].laps_down, 2);
+        assert_eq!(table.cars[1].gap, None);
+    }
+
+    #[test]
+    fn cars_without_positions_are_ordered_by_distance() {
+        let mut timing = Timing::default();
+
+        timing.update(&frame(1, &[(1, 0.2), (1, 0.8), (2, 0.1)], &[0, 0, 1]), 1000);
+
+        let order: Vec<usize> = timing.table().cars.iter().map(|c| c.car_idx).collect();
+
+        assert_eq!(order, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn cars_off_track_are_left_out() {
+        let mut timing = Timing::default();
+
+        timing.update(&frame(1, &[(1, 0.5), (-1, -1.0)], &[1, 0]), 1000);
+
+        assert_eq!(timing.table().cars.len(), 1);
+    }
+
+    #[test]
+    fn a_new_session_clears_timing() {
+        let mut timing = race();
+
+        assert!(timing.update(&frame(2, &[(0, 0.1), (0, 0.1)], &[0, 0]), 4000));
+
+        let table = timing.table();
+
+        assert_eq!(table.session_number, 2);
+        assert!(table.cars.iter().all(|c| c.laps_completed == 0 && c.last_lap.is_none()));
+    }
+}
@@ -0,0 +1,128 @@
+//! TLS termination using certificates which are reloaded when their files change.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+
+///
+/// Certificate resolver which serves the certificate and key currently on disk.
+///
+/// The files are checked for changes periodically; when they fail to load the previous
+/// certificate continues to be served.
+pub struct ReloadingResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Loaded>
+}
+
+struct Loaded {
+    key: CertifiedKey,
+    modified: (SystemTime, SystemTime)
+}
+
+/// Build a server configuration serving the given certificate chain and private key (both PEM).
+pub fn server_config(cert_path: &str, key_path: &str, reload_interval: Duration) -> io::Result<ServerConfig> {
+    let resolver = Arc::new(ReloadingResolver::new(cert_path, key_path)?);
+    let watcher = resolver.clone();
+
+    thread::spawn(move || loop {
+        thread::sleep(reload_interval);
+        watcher.reload_if_changed();
+    });
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = resolver;
+
+    Ok(config)
+}
+
+impl ReloadingResolver {
+    pub fn new(cert_path: &str, key_path: &str) -> io::Result<Self> {
+        let loaded = load(cert_path, key_path)?;
+
+        Ok(Self {
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+            current: RwLock::new(loaded)
+        })
+    }
+
+    fn reload_if_changed(&self) {
+        let modified = match modified(&self.cert_path, &self.key_path) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Unable to check TLS certificate for changes: {}", e);
+                return;
+            }
+        };
+
+        if self.current.read().map(|c| c.modified == modified).unwrap_or(false) {
+            return;
+        }
+
+        match load(&self.cert_path, &self.key_path) {
+            Ok(loaded) => {
+                info!("Reloaded TLS certificate from {}", self.cert_path);
+
+                if let Ok(mut current) = self.current.write() {
+                    *current = loaded;
+                }
+            },
+
+            Err(e) => error!("Unable to reload TLS certificate, continuing with previous certificate: {}", e)
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        self.current.read().ok().map(|c| c.key.clone())
+    }
+}
+
+fn load(cert_path: &str, key_path: &str) -> io::Result<Loaded> {
+    // Read the modification times first so a change made while loading is picked up next time.
+    let modified = modified(cert_path, key_path)?;
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| invalid(format!("Invalid certificate file {}", cert_path)))?;
+
+    if certs.is_empty() {
+        return Err(invalid(format!("No certificates found in {}", cert_path)));
+    }
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+        .map_err(|_| invalid(format!("Invalid private key file {}", key_path)))?;
+
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .map_err(|_| invalid(format!("Invalid private key file {}", key_path)))?;
+    }
+
+    let key = match keys.into_iter().next() {
+        Some(k) => k,
+        None => return Err(invalid(format!("No private key found in {}", key_path)))
+    };
+
+    let signer = sign::any_supported_type(&key)
+        .map_err(|_| invalid(format!("Unsupported private key type in {}", key_path)))?;
+
+    Ok(Loaded {
+        key: CertifiedKey::new(certs, Arc::new(signer)),
+        modified: modified
+    })
+}
+
+fn modified(cert_path: &str, key_path: &str) -> io::Result<(SystemTime, SystemTime)> {
+    Ok((fs::metadata(cert_path)?.modified()?, fs::metadata(key_path)?.modified()?))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}